@@ -0,0 +1,79 @@
+use crate::neighbor::Neighbor;
+use crate::protocol;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Instant;
+
+// 📒 Libreta de vecinos: los que respondieron alguna vez, para volver a buscarlos
+// al arrancar (sin tener que escribir su IP:PUERTO después de un corte de luz).
+const MAX_ENTRIES: usize = 256;
+pub const MAX_BOOTSTRAP: usize = 32;
+const FORGET_AFTER_SECS: u64 = 30 * 24 * 3600; // Un mes sin verlo
+
+#[derive(Serialize, Deserialize, Clone)]
+struct KnownPeer {
+    node_id: String, // hex
+    last_seen: u64,  // Segundos UNIX
+    #[serde(default)]
+    rtt_ms: Option<u64>,
+    #[serde(default)]
+    etx: Option<f64>,
+}
+
+pub struct AddressBook {
+    path: PathBuf,
+    peers: BTreeMap<SocketAddr, KnownPeer>,
+}
+
+impl AddressBook {
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let peers = fs::read_to_string(&path).ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self { path, peers }
+    }
+
+    /// Anota los vecinos que se presentaron con su sondeo (con la calidad de su enlace) y guarda
+    pub fn record<'a>(&mut self, neighbors: impl Iterator<Item = (&'a SocketAddr, &'a Neighbor)>) {
+        let now = Instant::now();
+        let now_secs = protocol::unix_millis() / 1000;
+        for (addr, neighbor) in neighbors {
+            let Some(node_id) = neighbor.node_id else { continue };
+            let seen_secs_ago = now.duration_since(neighbor.last_seen).as_secs();
+            self.peers.insert(*addr, KnownPeer {
+                node_id: hex::encode(node_id),
+                last_seen: now_secs.saturating_sub(seen_secs_ago),
+                rtt_ms: neighbor.rtt.map(|rtt| rtt.as_millis() as u64),
+                etx: neighbor.etx(now),
+            });
+        }
+        // Olvidamos los muy viejos y, si sobran, los menos recientes
+        self.peers.retain(|_, p| now_secs.saturating_sub(p.last_seen) < FORGET_AFTER_SECS);
+        while self.peers.len() > MAX_ENTRIES {
+            let Some(oldest) = self.peers.iter().min_by_key(|(_, p)| p.last_seen).map(|(addr, _)| *addr) else { break };
+            self.peers.remove(&oldest);
+        }
+        self.save();
+    }
+
+    /// A quién volver a llamar al arrancar: los vistos más recientemente primero
+    pub fn bootstrap(&self) -> Vec<SocketAddr> {
+        let mut known: Vec<(&SocketAddr, &KnownPeer)> = self.peers.iter().collect();
+        known.sort_by_key(|(_, p)| std::cmp::Reverse(p.last_seen));
+        known.into_iter().take(MAX_BOOTSTRAP).map(|(addr, _)| *addr).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    fn save(&self) {
+        if let Ok(json) = serde_json::to_string_pretty(&self.peers) {
+            let _ = fs::write(&self.path, json);
+        }
+    }
+}
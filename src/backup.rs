@@ -0,0 +1,153 @@
+// 🧳 Subcomandos para llevar la identidad de una máquina a otra:
+//   identity fingerprint                  Muestra ID, llave pública y huella
+//   identity export <ARCHIVO>             Respaldo cifrado con otra frase
+//   identity import <ARCHIVO> [--force]   Restaura un respaldo cifrado
+//   identity paper                        Respaldo cifrado en base32 para imprimir
+//   identity restore-paper [--force]      Restaura el respaldo en papel (por stdin)
+//   identity rotate                       Cambia a una llave nueva y anuncia la rotación
+//   identity revoke-bundle <ARCHIVO>      Certificado de revocación para guardar aparte
+// Todos actúan sobre el perfil elegido con --profile (y --data-dir).
+
+use crate::config::{self, ProfileFlags};
+use crate::identity::{self, EncryptedSecret, Identity, Unlock};
+use crate::protocol::KeyUpdate;
+use crate::revocation::RevokedKeys;
+use data_encoding::BASE32_NOPAD;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "Uso: cargo run identity <fingerprint|export|import|paper|restore-paper|rotate|revoke-bundle> [ARCHIVO] [--profile <NOMBRE>] [--data-dir <RUTA>] [--force] [--identity-key-file <RUTA>]
+Variables de entorno:
+  EMBER_BACKUP_PASSPHRASE    Frase del respaldo (sin preguntar)";
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut positional = Vec::new();
+    let mut force = false;
+    let mut identity_key_file: Option<PathBuf> = None;
+    let mut profile_flags = ProfileFlags::default();
+
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--force" => force = true,
+            "--identity-key-file" => identity_key_file = Some(PathBuf::from(config::flag_value(&mut it, arg)?)),
+            "--profile" | "--data-dir" => profile_flags.parse(&mut it, arg)?,
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    let Some(command) = positional.first() else {
+        return Err(USAGE.to_string());
+    };
+    let profile = profile_flags.open()?;
+    let identity_path = profile.identity_path();
+    let identity_path = identity_path.as_path();
+    let unlock = config::identity_unlock(identity_key_file)?;
+    println!("🗂️ Perfil '{}' ({})", profile.name, profile.dir.display());
+
+    match (*command, positional.get(1)) {
+        ("fingerprint", _) => {
+            let pubkey = Identity::read_public_key(identity_path)?;
+            print_fingerprint(&pubkey);
+        },
+        ("export", Some(out)) => {
+            let id = Identity::load(identity_path, &unlock)?;
+            let passphrase = backup_unlock().passphrase("Frase para el respaldo", true)?;
+            let sealed = EncryptedSecret::seal(&id.signing, &passphrase)?;
+            let json = serde_json::to_string_pretty(&sealed).map_err(|e| e.to_string())?;
+            fs::write(out, json).map_err(|e| format!("No se pudo escribir {}: {}", out, e))?;
+            println!("📦 Respaldo cifrado guardado en {}", out);
+            print_fingerprint(&id.verify.to_bytes());
+        },
+        ("import", Some(input)) => {
+            refuse_overwrite(identity_path, force)?;
+            let json = fs::read_to_string(input).map_err(|e| format!("No se pudo leer {}: {}", input, e))?;
+            let sealed: EncryptedSecret = serde_json::from_str(&json).map_err(|_| format!("{} no es un respaldo válido", input))?;
+            let id = Identity::unseal(&sealed, &backup_unlock(), "Frase del respaldo")?;
+            id.save(identity_path, &unlock)?;
+            print_fingerprint(&id.verify.to_bytes());
+        },
+        ("paper", _) => {
+            let id = Identity::load(identity_path, &unlock)?;
+            let passphrase = backup_unlock().passphrase("Frase para el respaldo en papel", true)?;
+            let mut data = EncryptedSecret::seal(&id.signing, &passphrase)?.to_compact()?;
+            let checksum = Sha256::digest(&data);
+            data.extend_from_slice(&checksum[..4]);
+
+            println!("=== EMBER · RESPALDO DE IDENTIDAD (cifrado) ===");
+            print_fingerprint(&id.verify.to_bytes());
+            println!();
+            let encoded = BASE32_NOPAD.encode(&data);
+            let groups: Vec<&str> = encoded.as_bytes().chunks(4).map(|c| std::str::from_utf8(c).unwrap()).collect();
+            for line in groups.chunks(8) { println!("  {}", line.join(" ")); }
+            println!();
+            println!("Para restaurar: cargo run identity restore-paper --profile <NOMBRE> y pega las líneas.");
+        },
+        ("restore-paper", _) => {
+            refuse_overwrite(identity_path, force)?;
+            println!("Pega el respaldo y termina con una línea vacía:");
+            let mut encoded = String::new();
+            for line in io::stdin().lock().lines() {
+                let line = line.map_err(|e| e.to_string())?;
+                if line.trim().is_empty() { if encoded.is_empty() { continue; } break; }
+                encoded.extend(line.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_uppercase()));
+            }
+            let data = BASE32_NOPAD.decode(encoded.as_bytes()).map_err(|_| "El respaldo tiene caracteres inválidos")?;
+            if data.len() < 4 { return Err("Respaldo incompleto".to_string()); }
+            let (body, checksum) = data.split_at(data.len() - 4);
+            if Sha256::digest(body)[..4] != *checksum {
+                return Err("El respaldo tiene un error de tipeo (checksum no coincide)".to_string());
+            }
+            let sealed = EncryptedSecret::from_compact(body)?;
+            let id = Identity::unseal(&sealed, &backup_unlock(), "Frase del respaldo")?;
+            id.save(identity_path, &unlock)?;
+            print_fingerprint(&id.verify.to_bytes());
+        },
+        ("rotate", _) => {
+            let old = Identity::load(identity_path, &unlock)?;
+            let new = Identity::generate();
+            // El certificado queda en el perfil: el nodo lo anunciará al arrancar
+            let mut revoked = RevokedKeys::load(profile.revoked_path())?;
+            revoked.insert(KeyUpdate::rotate(&old, &new));
+            new.save(identity_path, &unlock)?;
+            // Las sesiones se acordaron con la llave vieja
+            let _ = fs::remove_dir_all(profile.sessions_dir());
+            println!("🔄 Llave rotada: {} -> {}", hex::encode(old.node_id()), hex::encode(new.node_id()));
+            print_fingerprint(&new.verify.to_bytes());
+        },
+        ("revoke-bundle", Some(out)) => {
+            refuse_overwrite(Path::new(out), force)?;
+            let id = Identity::load(identity_path, &unlock)?;
+            let json = serde_json::to_string_pretty(&KeyUpdate::revoke(&id)).map_err(|e| e.to_string())?;
+            fs::write(out, json).map_err(|e| format!("No se pudo escribir {}: {}", out, e))?;
+            println!("🪦 Revocación guardada en {} (guárdala lejos de este equipo).", out);
+            println!("   Si lo pierdes, anúnciala desde otro nodo con: /announce {}", out);
+            print_fingerprint(&id.verify.to_bytes());
+        },
+        _ => return Err(USAGE.to_string()),
+    }
+    Ok(())
+}
+
+fn backup_unlock() -> Unlock {
+    match env::var("EMBER_BACKUP_PASSPHRASE") {
+        Ok(phrase) => Unlock::Passphrase(phrase),
+        Err(_) => Unlock::Prompt,
+    }
+}
+
+fn refuse_overwrite(path: &Path, force: bool) -> Result<(), String> {
+    if path.exists() && !force {
+        return Err(format!("{} ya existe (usa --force para reemplazarla)", path.display()));
+    }
+    Ok(())
+}
+
+fn print_fingerprint(pubkey: &[u8; 32]) {
+    println!("🆔 NODE ID: {}", hex::encode(identity::node_id_from_pubkey(pubkey)));
+    println!("🔑 LLAVE:   {}", hex::encode(pubkey));
+    println!("🧬 HUELLA:  {}", identity::fingerprint(pubkey));
+}
@@ -0,0 +1,66 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+/// 🚫 Nodos bloqueados (sus tramas se descartan) y silenciados (no se muestra su chat)
+#[derive(Serialize, Deserialize, Default)]
+struct Lists {
+    blocked: BTreeSet<String>, // node_id en hex
+    muted: BTreeSet<String>,
+}
+
+pub struct BlockList {
+    path: PathBuf,
+    lists: Lists,
+}
+
+impl BlockList {
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let lists = fs::read_to_string(&path).ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self { path, lists }
+    }
+
+    pub fn is_blocked(&self, node_id: &[u8; 8]) -> bool {
+        self.lists.blocked.contains(&hex::encode(node_id))
+    }
+
+    pub fn is_muted(&self, node_id: &[u8; 8]) -> bool {
+        self.lists.muted.contains(&hex::encode(node_id))
+    }
+
+    /// Devuelve false si ya estaba así
+    pub fn set_blocked(&mut self, node_id: &[u8; 8], blocked: bool) -> bool {
+        let changed = Self::toggle(&mut self.lists.blocked, node_id, blocked);
+        if changed { self.save(); }
+        changed
+    }
+
+    pub fn set_muted(&mut self, node_id: &[u8; 8], muted: bool) -> bool {
+        let changed = Self::toggle(&mut self.lists.muted, node_id, muted);
+        if changed { self.save(); }
+        changed
+    }
+
+    pub fn blocked(&self) -> impl Iterator<Item = &String> {
+        self.lists.blocked.iter()
+    }
+
+    pub fn muted(&self) -> impl Iterator<Item = &String> {
+        self.lists.muted.iter()
+    }
+
+    fn toggle(set: &mut BTreeSet<String>, node_id: &[u8; 8], on: bool) -> bool {
+        let id_hex = hex::encode(node_id);
+        if on { set.insert(id_hex) } else { set.remove(&id_hex) }
+    }
+
+    fn save(&self) {
+        if let Ok(json) = serde_json::to_string_pretty(&self.lists) {
+            let _ = fs::write(&self.path, json);
+        }
+    }
+}
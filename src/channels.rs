@@ -0,0 +1,321 @@
+use crate::crypto;
+use crate::identity::{node_id_from_pubkey, Identity};
+use ed25519_dalek::{Signature, Signer, Verifier, VerifyingKey};
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+/// 📢 Mensaje de canal: viaja como difusión (cifrado con la clave de red) y
+/// adentro va el texto cifrado con la llave del canal.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelMessage {
+    pub channel_id: [u8; 8],
+    pub ciphertext: Vec<u8>,
+}
+
+/// 📨 Estado de un canal firmado por su admin: llave, época y lista de miembros.
+/// Viaja solo por DM (sesión de trinquete). Cada cambio de miembros sube la época;
+/// al sacar a alguien además cambia la llave (y al expulsado no se la mandamos).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelInvite {
+    pub name: String,
+    pub key: [u8; 32],
+    pub epoch: u64,
+    pub members: Vec<[u8; 8]>,
+    pub admin_pubkey: [u8; 32],
+    pub signature: Vec<u8>,
+}
+
+impl ChannelInvite {
+    /// Lo que firma el admin (la llave entra como hash: no hace falta firmarla en claro)
+    fn statement(&self) -> Vec<u8> {
+        let mut data = b"EMBER-CHANNEL-v1".to_vec();
+        data.extend_from_slice(&(self.name.len() as u32).to_le_bytes());
+        data.extend_from_slice(self.name.as_bytes());
+        data.extend_from_slice(&self.epoch.to_le_bytes());
+        data.extend_from_slice(&Sha256::digest(self.key));
+        data.extend_from_slice(&self.admin_pubkey);
+        for member in &self.members { data.extend_from_slice(member); }
+        data
+    }
+
+    fn signed(mut self, admin: &Identity) -> Self {
+        self.signature = admin.signing.sign(&self.statement()).to_bytes().to_vec();
+        self
+    }
+
+    fn is_valid(&self) -> bool {
+        let Ok(key) = VerifyingKey::from_bytes(&self.admin_pubkey) else { return false };
+        let Ok(sig_bytes) = <[u8; 64]>::try_from(self.signature.as_slice()) else { return false };
+        key.verify(&self.statement(), &Signature::from_bytes(&sig_bytes)).is_ok()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChannelState {
+    key_hex: String,
+    joined: bool, // false = solo invitación recibida
+    #[serde(default)]
+    admin_hex: String,
+    #[serde(default)]
+    epoch: u64,
+    #[serde(default)]
+    members: BTreeSet<String>, // node_id en hex
+    // 📮 (Solo el admin) Miembros que todavía no confirmaron la época actual
+    #[serde(default)]
+    owed: BTreeSet<String>,
+}
+
+impl ChannelState {
+    fn key(&self) -> Option<[u8; 32]> {
+        hex::decode(&self.key_hex).ok()?.try_into().ok()
+    }
+
+    fn admin(&self) -> Option<[u8; 32]> {
+        hex::decode(&self.admin_hex).ok()?.try_into().ok()
+    }
+
+    fn member_ids(&self) -> Vec<[u8; 8]> {
+        self.members.iter().filter_map(|m| hex::decode(m).ok()?.try_into().ok()).collect()
+    }
+
+    fn to_invite(&self, name: &str) -> Option<ChannelInvite> {
+        Some(ChannelInvite {
+            name: name.to_string(),
+            key: self.key()?,
+            epoch: self.epoch,
+            members: self.member_ids(),
+            admin_pubkey: self.admin()?,
+            signature: Vec::new(),
+        })
+    }
+}
+
+/// El ID depende de la llave: quien no está en el canal no puede saber de cuál es un mensaje
+pub fn channel_id(key: &[u8; 32]) -> [u8; 8] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"EMBER-CHANNEL-ID-v1");
+    hasher.update(key);
+    let digest = hasher.finalize();
+    let mut id = [0u8; 8];
+    id.copy_from_slice(&digest[..8]);
+    id
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    name.len() > 1 && name.len() <= 32 && name.starts_with('#')
+        && name[1..].chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Vista de un canal para /members
+pub struct Roster {
+    pub admin: [u8; 8],
+    pub epoch: u64,
+    pub members: Vec<[u8; 8]>,
+}
+
+/// Canales conocidos (nombre -> llave, admin y miembros), guardados en el perfil
+pub struct ChannelStore {
+    path: PathBuf,
+    channels: BTreeMap<String, ChannelState>,
+    // 📮 Estados mandados por DM esperando su ACK: msg_id -> (miembro, canal, época)
+    acks: HashMap<u64, ([u8; 8], String, u64)>,
+}
+
+impl ChannelStore {
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let channels = fs::read_to_string(&path).ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self { path, channels, acks: HashMap::new() }
+    }
+
+    /// Entra a un canal del que ya tenemos la invitación (y la llave)
+    pub fn join(&mut self, name: &str) -> Result<(), String> {
+        let state = self.channels.get_mut(name)
+            .ok_or(format!("no conocemos {}: espera la invitación del admin o créalo con /create {}", name, name))?;
+        state.joined = true;
+        self.save();
+        Ok(())
+    }
+
+    /// Crea un canal nuevo con llave propia, quedando como admin. Si ya lo conocemos
+    /// (p. ej. llegó la invitación) no se pisa: hay que entrar con /join.
+    pub fn create(&mut self, name: &str, me: &Identity) -> Result<(), String> {
+        if self.channels.contains_key(name) { return Err(format!("{} ya existe: entra con /join {}", name, name)); }
+        self.channels.insert(name.to_string(), ChannelState {
+            key_hex: hex::encode(random_key()),
+            joined: true,
+            admin_hex: hex::encode(me.verify.to_bytes()),
+            epoch: 0,
+            members: BTreeSet::from([hex::encode(me.node_id())]),
+            owed: BTreeSet::new(),
+        });
+        self.save();
+        Ok(())
+    }
+
+    /// Sale del canal pero guarda llave, admin y época: el admin puede volver con /join y
+    /// seguir administrándolo (un /create empezaría de nuevo en la época 0)
+    pub fn leave(&mut self, name: &str) -> bool {
+        let Some(state) = self.channels.get_mut(name).filter(|c| c.joined) else { return false };
+        state.joined = false;
+        self.save();
+        true
+    }
+
+    /// Aplica un estado recibido por DM. El primer admin que vemos queda fijado para
+    /// ese canal; después solo aceptamos cambios suyos y con una época mayor.
+    pub fn apply(&mut self, invite: &ChannelInvite, my_id: &[u8; 8]) -> Result<String, String> {
+        if !is_valid_name(&invite.name) { return Err("nombre de canal inválido".to_string()); }
+        if !invite.is_valid() { return Err("firma del admin inválida".to_string()); }
+        if !invite.members.contains(my_id) { return Err("no figuramos como miembros".to_string()); }
+
+        let admin_hex = hex::encode(invite.admin_pubkey);
+        let existing = self.channels.get(&invite.name);
+        if let Some(current) = existing {
+            if current.admin_hex != admin_hex { return Err(format!("{} tiene otro admin", invite.name)); }
+            if invite.epoch <= current.epoch { return Err("época vieja (repetida o fuera de orden)".to_string()); }
+        }
+
+        let rekeyed = existing.is_some_and(|c| c.key_hex != hex::encode(invite.key));
+        let joined = existing.is_some_and(|c| c.joined);
+        let outcome = match existing {
+            None => format!("📨 Invitación a {}. Entra con /join {}", invite.name, invite.name),
+            Some(_) if rekeyed => format!("🔐 {} cambió de llave (época {}): alguien fue removido", invite.name, invite.epoch),
+            Some(_) => format!("👥 Miembros de {} actualizados (época {})", invite.name, invite.epoch),
+        };
+        self.channels.insert(invite.name.clone(), ChannelState {
+            key_hex: hex::encode(invite.key),
+            joined,
+            admin_hex,
+            epoch: invite.epoch,
+            members: invite.members.iter().map(hex::encode).collect(),
+            owed: BTreeSet::new(),
+        });
+        self.save();
+        Ok(outcome)
+    }
+
+    /// ➕ (Solo el admin) Agrega un miembro. Devuelve el estado firmado a repartir.
+    pub fn add_member(&mut self, name: &str, member: [u8; 8], me: &Identity) -> Result<ChannelInvite, String> {
+        self.change_roster(name, me, |state| {
+            if !state.members.insert(hex::encode(member)) { return Err("ya es miembro".to_string()); }
+            Ok(())
+        })
+    }
+
+    /// ➖ (Solo el admin) Saca a un miembro y cambia la llave del canal
+    pub fn remove_member(&mut self, name: &str, member: [u8; 8], me: &Identity) -> Result<ChannelInvite, String> {
+        if member == me.node_id() { return Err("el admin no puede sacarse a sí mismo".to_string()); }
+        self.change_roster(name, me, |state| {
+            if !state.members.remove(&hex::encode(member)) { return Err("no es miembro".to_string()); }
+            state.key_hex = hex::encode(random_key());
+            Ok(())
+        })
+    }
+
+    fn change_roster(&mut self, name: &str, me: &Identity, change: impl FnOnce(&mut ChannelState) -> Result<(), String>) -> Result<ChannelInvite, String> {
+        let state = self.channels.get_mut(name).filter(|c| c.joined).ok_or(format!("no estás en {}", name))?;
+        if state.admin() != Some(me.verify.to_bytes()) {
+            let admin = state.admin().map(|a| hex::encode(node_id_from_pubkey(&a))).unwrap_or_default();
+            return Err(format!("solo el admin de {} ({}) puede cambiar los miembros", name, admin));
+        }
+        change(state)?;
+        state.epoch += 1;
+        // La época nueva se le debe a cada miembro hasta que la confirme
+        let me_hex = hex::encode(me.node_id());
+        state.owed = state.members.iter().filter(|m| **m != me_hex).cloned().collect();
+        let invite = state.to_invite(name).ok_or("canal corrupto")?.signed(me);
+        self.save();
+        Ok(invite)
+    }
+
+    /// ¿Le debemos a `member` el estado de algún canal? (se lo mandamos al llegar su llave)
+    pub fn owes(&self, member: &[u8; 8]) -> bool {
+        let member_hex = hex::encode(member);
+        self.channels.values().any(|c| c.owed.contains(&member_hex))
+    }
+
+    /// Lo que debemos (miembro, estado actual firmado de nuevo) de los canales que administramos.
+    /// Los que ya no son miembros (o canales que no son nuestros) se olvidan.
+    pub fn owed_invites(&mut self, me: &Identity) -> Vec<([u8; 8], ChannelInvite)> {
+        let mut owed = Vec::new();
+        let mut changed = false;
+        for (name, state) in self.channels.iter_mut() {
+            if state.owed.is_empty() { continue; }
+            let before = state.owed.len();
+            if state.admin() != Some(me.verify.to_bytes()) { state.owed.clear(); }
+            let members = &state.members;
+            state.owed.retain(|m| members.contains(m));
+            changed |= state.owed.len() != before;
+            let Some(invite) = state.to_invite(name) else { continue };
+            let invite = invite.signed(me);
+            for member in &state.owed {
+                if let Some(id) = hex::decode(member).ok().and_then(|b| <[u8; 8]>::try_from(b).ok()) {
+                    owed.push((id, invite.clone()));
+                }
+            }
+        }
+        if changed { self.save(); }
+        owed
+    }
+
+    /// Mandamos el estado por DM con este msg_id: su ACK salda la deuda
+    pub fn awaiting_ack(&mut self, msg_id: u64, member: [u8; 8], invite: &ChannelInvite) {
+        self.acks.retain(|_, (m, name, _)| !(*m == member && *name == invite.name));
+        self.acks.insert(msg_id, (member, invite.name.clone(), invite.epoch));
+    }
+
+    /// ACK de un DM: si era un estado de canal (de la época actual), ya no se lo debemos
+    pub fn on_ack(&mut self, msg_id: u64, from: &[u8; 8]) -> Option<String> {
+        let (member, name, epoch) = self.acks.get(&msg_id).filter(|(m, _, _)| m == from)?.clone();
+        self.acks.remove(&msg_id);
+        let state = self.channels.get_mut(&name).filter(|c| c.epoch == epoch)?;
+        if !state.owed.remove(&hex::encode(member)) { return None; }
+        self.save();
+        Some(name)
+    }
+
+    pub fn roster(&self, name: &str) -> Option<Roster> {
+        let state = self.channels.get(name)?;
+        Some(Roster { admin: node_id_from_pubkey(&state.admin()?), epoch: state.epoch, members: state.member_ids() })
+    }
+
+    pub fn seal(&self, name: &str, text: &[u8]) -> Option<ChannelMessage> {
+        let key = self.channels.get(name).filter(|c| c.joined)?.key()?;
+        Some(ChannelMessage { channel_id: channel_id(&key), ciphertext: crypto::encrypt_with(&key, &crypto::pad(text)) })
+    }
+
+    /// Descifra un mensaje si es de un canal en el que estamos: (nombre, texto, ¿el emisor es miembro?)
+    pub fn open(&self, msg: &ChannelMessage, sender: &[u8; 8]) -> Option<(&str, Vec<u8>, bool)> {
+        self.channels.iter()
+            .filter(|(_, c)| c.joined)
+            .find_map(|(name, c)| {
+                let key = c.key()?;
+                if channel_id(&key) != msg.channel_id { return None; }
+                let text = crypto::unpad(&crypto::decrypt_with(&key, &msg.ciphertext)?)?;
+                Some((name.as_str(), text, c.members.contains(&hex::encode(sender))))
+            })
+    }
+
+    pub fn joined(&self) -> impl Iterator<Item = &String> {
+        self.channels.iter().filter(|(_, c)| c.joined).map(|(name, _)| name)
+    }
+
+    fn save(&self) {
+        if let Ok(json) = serde_json::to_string_pretty(&self.channels) {
+            let _ = fs::write(&self.path, json);
+        }
+    }
+}
+
+fn random_key() -> [u8; 32] {
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    key
+}
@@ -12,9 +12,12 @@ pub struct Chunk {
     pub data: Vec<u8>,
 }
 
+// (total de piezas, piezas recibidas, momento de la primera pieza)
+//                👇 u32 aquí también
+type PendingMessage = (u32, HashMap<u32, Vec<u8>>, Instant);
+
 pub struct Assembler {
-    //                                👇 u32 aquí también
-    buffer: HashMap<u64, (u32, HashMap<u32, Vec<u8>>, Instant)>,
+    buffer: HashMap<u64, PendingMessage>,
 }
 
 impl Assembler {
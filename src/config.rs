@@ -0,0 +1,200 @@
+use crate::crypto::NetworkKeySource;
+use crate::identity::Unlock;
+use crate::pow::MAX_POW_BITS;
+use crate::transport;
+use crate::profile::{Profile, DEFAULT_PROFILE};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Uso: cargo run <MI_PUERTO> [IP_VECINO:PUERTO | [IPv6%interfaz]:PUERTO] [opciones]
+     cargo run identity <fingerprint|export|import|paper|restore-paper|rotate|revoke-bundle> ...
+     cargo run profiles
+Opciones:
+  --profile <NOMBRE>         Perfil (identidad) a usar; por defecto \"default\"
+  --config <RUTA>            Archivo con vecinos de arranque (por defecto config.json del perfil):
+                             { \"bootstrap\": [\"IP:PUERTO\", ...] }
+  --data-dir <RUTA>          Carpeta de datos (perfiles, llaves, sesiones)
+  --net-key-file <RUTA>      Clave de red: 32 bytes crudos o 64 caracteres hex
+  --net-passphrase <FRASE>   Clave de red derivada de una frase (Argon2); visible en `ps`,
+                             mejor EMBER_NET_PASSPHRASE o --net-key-file
+  --identity-key-file <RUTA> Archivo con la frase que abre la identidad
+  --privacy                  Rellena las tramas a tamaños fijos (oculta su tipo por el tamaño)
+  --cover-traffic <SEGUNDOS> Envía chats falsos cada ~N segundos (activa --privacy)
+  --seal-headers             Cifra también las cabeceras: oculta quién habla con quién a los de
+                             afuera (los miembros de la malla sí lo ven); requiere clave de red propia
+  --on-demand                Busca rutas solo al necesitarlas (RREQ/RREP) en vez de anunciarlas
+  --no-discovery             No anuncia ni busca nodos en la LAN por multicast (modo sigiloso)
+  --relay-blocked            Reenvía las tramas de los nodos bloqueados (sin mostrarlas)
+  --pow-bits <N>             Exige (y calcula) un sello de prueba de trabajo de N bits por identidad
+Variables de entorno:
+  EMBER_NET_KEY              Clave de red en hex (64 caracteres)
+  EMBER_NET_PASSPHRASE       Frase para derivar la clave de red
+  EMBER_IDENTITY_PASSPHRASE  Frase que abre la identidad (sin preguntar)
+  EMBER_PROFILE              Perfil por defecto
+  EMBER_HOME                 Carpeta de datos por defecto
+  EMBER_POW_BITS             Bits de prueba de trabajo por defecto (0 = desactivado)";
+
+/// Configuración de arranque leída de la línea de comandos y del entorno
+pub struct Config {
+    pub port: u16,
+    pub initial_peer: Option<SocketAddr>,
+    pub net_key: NetworkKeySource,
+    pub identity_unlock: Unlock,
+    pub profile: Profile,
+    pub pow_bits: u8, // 0 = sin prueba de trabajo
+    pub relay_blocked: bool,
+    pub privacy: bool,
+    pub cover_traffic: Option<u64>, // Segundos promedio entre tramas de cobertura
+    pub seal_headers: bool,
+    pub on_demand: bool, // 🔎 Enrutamiento bajo demanda (ver routing.rs)
+    pub discovery: bool, // 📡 Beacons multicast en la LAN
+    pub bootstrap: Vec<SocketAddr>, // 📒 Vecinos de arranque del archivo de configuración
+}
+
+/// Archivo de configuración (JSON)
+#[derive(Deserialize, Default)]
+struct FileConfig {
+    #[serde(default)]
+    bootstrap: Vec<String>,
+}
+
+impl Config {
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut net_key_file: Option<PathBuf> = None;
+        let mut net_passphrase: Option<String> = None;
+        let mut identity_key_file: Option<PathBuf> = None;
+        let mut profile_flags = ProfileFlags::default();
+        let mut pow_bits = env::var("EMBER_POW_BITS").ok();
+        let mut relay_blocked = false;
+        let mut privacy = false;
+        let mut seal_headers = false;
+        let mut on_demand = false;
+        let mut discovery = true;
+        let mut config_file: Option<PathBuf> = None;
+        let mut cover_traffic: Option<u64> = None;
+
+        let mut it = args.iter().skip(1);
+        while let Some(arg) = it.next() {
+            match arg.as_str() {
+                "--net-key-file" => net_key_file = Some(PathBuf::from(flag_value(&mut it, arg)?)),
+                "--net-passphrase" => net_passphrase = Some(flag_value(&mut it, arg)?),
+                "--identity-key-file" => identity_key_file = Some(PathBuf::from(flag_value(&mut it, arg)?)),
+                "--profile" | "--data-dir" => profile_flags.parse(&mut it, arg)?,
+                "--relay-blocked" => relay_blocked = true,
+                "--privacy" => privacy = true,
+                "--seal-headers" => seal_headers = true,
+                "--on-demand" => on_demand = true,
+                "--no-discovery" => discovery = false,
+                "--config" => config_file = Some(PathBuf::from(flag_value(&mut it, arg)?)),
+                "--cover-traffic" => {
+                    let secs = flag_value(&mut it, arg)?;
+                    cover_traffic = Some(secs.parse().ok().filter(|s| *s > 0)
+                        .ok_or_else(|| format!("--cover-traffic espera segundos (> 0), no '{}'", secs))?);
+                },
+                "--pow-bits" => pow_bits = Some(flag_value(&mut it, arg)?),
+                _ if arg.starts_with("--") => return Err(format!("Opción desconocida: {}", arg)),
+                _ => positional.push(arg.clone()),
+            }
+        }
+
+        let port: u16 = positional.first()
+            .ok_or("Falta el puerto")?
+            .parse().map_err(|_| "Puerto inválido")?;
+        let initial_peer = match positional.get(1) {
+            Some(p) => Some(transport::parse_addr(p)?),
+            None => None,
+        };
+
+        // Prioridad: opciones (archivo > frase) > entorno (clave hex > frase) > clave de fábrica
+        let net_key = if let Some(path) = net_key_file {
+            NetworkKeySource::File(path)
+        } else if let Some(phrase) = net_passphrase {
+            NetworkKeySource::Passphrase(phrase)
+        } else if let Ok(hex_key) = env::var("EMBER_NET_KEY") {
+            NetworkKeySource::Hex(hex_key)
+        } else if let Ok(phrase) = env::var("EMBER_NET_PASSPHRASE") {
+            NetworkKeySource::Passphrase(phrase)
+        } else {
+            NetworkKeySource::Default
+        };
+
+        let pow_bits: u8 = match pow_bits {
+            Some(bits) => bits.parse().ok().filter(|b| *b <= MAX_POW_BITS)
+                .ok_or_else(|| format!("--pow-bits debe ser un número entre 0 y {}", MAX_POW_BITS))?,
+            None => 0,
+        };
+
+        // La cobertura solo se confunde con el tráfico real si todo va rellenado
+        let privacy = privacy || cover_traffic.is_some();
+        // La clave de fábrica viene en el binario: con ella cualquiera abre las cabeceras y el relleno
+        if (privacy || seal_headers) && matches!(net_key, NetworkKeySource::Default) {
+            return Err("--privacy, --cover-traffic y --seal-headers necesitan una clave de red propia (--net-key-file o --net-passphrase): la de fábrica es pública".into());
+        }
+
+        let identity_unlock = identity_unlock(identity_key_file)?;
+        let profile = profile_flags.open()?;
+        let bootstrap = load_bootstrap(config_file, &profile)?;
+
+        Ok(Self { port, initial_peer, net_key, identity_unlock, profile, pow_bits, relay_blocked, privacy, cover_traffic, seal_headers, on_demand, discovery, bootstrap })
+    }
+}
+
+/// Vecinos de arranque: el archivo de --config (que debe existir) o, si está, el config.json del perfil
+fn load_bootstrap(config_file: Option<PathBuf>, profile: &Profile) -> Result<Vec<SocketAddr>, String> {
+    let file_config: FileConfig = match config_file {
+        Some(path) => {
+            let json = fs::read_to_string(&path).map_err(|e| format!("No se pudo leer {}: {}", path.display(), e))?;
+            serde_json::from_str(&json).map_err(|e| format!("{} no es válido: {}", path.display(), e))?
+        },
+        None => match fs::read_to_string(profile.config_path()) {
+            Ok(json) => serde_json::from_str(&json).map_err(|e| format!("{} no es válido: {}", profile.config_path().display(), e))?,
+            Err(_) => FileConfig::default(),
+        },
+    };
+    file_config.bootstrap.iter().map(|addr| transport::parse_addr(addr)).collect()
+}
+
+/// --profile y --data-dir (compartidos con los subcomandos)
+#[derive(Default)]
+pub struct ProfileFlags {
+    pub name: Option<String>,
+    pub data_dir: Option<PathBuf>,
+}
+
+impl ProfileFlags {
+    pub fn parse<'a>(&mut self, it: &mut impl Iterator<Item = &'a String>, flag: &str) -> Result<(), String> {
+        let value = flag_value(it, flag)?;
+        match flag {
+            "--profile" => self.name = Some(value),
+            _ => self.data_dir = Some(PathBuf::from(value)),
+        }
+        Ok(())
+    }
+
+    pub fn open(self) -> Result<Profile, String> {
+        let name = self.name
+            .or_else(|| env::var("EMBER_PROFILE").ok())
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+        Profile::open(&Profile::data_dir(self.data_dir), &name)
+    }
+}
+
+/// Frase de la identidad: archivo > variable de entorno > preguntar en la terminal
+pub fn identity_unlock(key_file: Option<PathBuf>) -> Result<Unlock, String> {
+    if let Some(path) = key_file {
+        let phrase = fs::read_to_string(&path).map_err(|e| format!("No se pudo leer {}: {}", path.display(), e))?;
+        Ok(Unlock::Passphrase(phrase.trim_end_matches(['\r', '\n']).to_string()))
+    } else if let Ok(phrase) = env::var("EMBER_IDENTITY_PASSPHRASE") {
+        Ok(Unlock::Passphrase(phrase))
+    } else {
+        Ok(Unlock::Prompt)
+    }
+}
+
+pub fn flag_value<'a>(it: &mut impl Iterator<Item = &'a String>, flag: &str) -> Result<String, String> {
+    it.next().cloned().ok_or_else(|| format!("Falta el valor de {}", flag))
+}
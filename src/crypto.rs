@@ -1,20 +1,160 @@
 use chacha20poly1305::{
     aead::{Aead, KeyInit, OsRng},
-    XChaCha20Poly1305, XNonce 
+    XChaCha20Poly1305, XNonce
 };
+use argon2::Argon2;
 use rand::RngCore;
+use sha2::{Sha256, Digest};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
 
-// 🔑 CLAVE MAESTRA DE LA RED (32 bytes)
-// En un futuro, esto podría negociarse con Diffie-Hellman (X25519).
-// Por ahora, usamos una clave compartida (Pre-Shared Key) para que funcione.
-// ¡Si cambias una letra aquí, los nodos dejarán de entenderse!
-const NETWORK_KEY: &[u8; 32] = b"EMBER_MESH_SECRET_KEY_v1_2024_OK";
+// 🔑 CLAVE DE RED DE FÁBRICA (32 bytes)
+// Solo se usa si no se configuró otra con --net-key-file, EMBER_NET_KEY o una frase.
+// Todos los binarios la conocen: sirve para pruebas, no para una malla real.
+const DEFAULT_NETWORK_KEY: &[u8; 32] = b"EMBER_MESH_SECRET_KEY_v1_2024_OK";
 
-/// Encripta los datos usando XChaCha20-Poly1305
-/// Devuelve: [NONCE (24 bytes) | CIPHERTEXT (datos cifrados)]
+// Sal fija para derivar la clave desde una frase: todos los nodos de una malla
+// deben llegar a la misma clave partiendo de la misma frase.
+const PASSPHRASE_SALT: &[u8] = b"EMBER-MESH-NETWORK-KEY-v1";
+
+// 📏 Sobre interno de todo contenido cifrado de una trama:
+// [tipo (1) | largo (4) | datos | relleno en ceros]. En modo privacidad el relleno
+// lleva el sobre a un tamaño fijo (BUCKETS), así el tamaño de la trama ya no
+// delata si es un chat corto o un pedazo de archivo.
+const ENVELOPE_DATA: u8 = 0;
+const ENVELOPE_COVER: u8 = 1; // Tráfico de cobertura: se descarta al descifrar
+const ENVELOPE_HEADER: usize = 5;
+const BUCKETS: [usize; 3] = [256, 512, 1024]; // Más allá: múltiplos de 1024
+static PADDING: OnceLock<bool> = OnceLock::new();
+
+// Clave de red activa (protege el tráfico de difusión: Hello, chat general, Acks).
+// Los mensajes directos (/dm) usan una llave por pareja acordada con X25519.
+static NETWORK_KEY: OnceLock<[u8; 32]> = OnceLock::new();
+
+/// De dónde se obtiene la clave de red al arrancar
+pub enum NetworkKeySource {
+    File(PathBuf),
+    Hex(String),
+    Passphrase(String),
+    Default,
+}
+
+/// Carga (o deriva) la clave de red según la fuente configurada
+pub fn load_network_key(source: &NetworkKeySource) -> Result<[u8; 32], String> {
+    match source {
+        NetworkKeySource::File(path) => {
+            let raw = fs::read(path).map_err(|e| format!("No se pudo leer {}: {}", path.display(), e))?;
+            // Aceptamos 32 bytes crudos o su versión en hex
+            if raw.len() == 32 {
+                let mut key = [0u8; 32];
+                key.copy_from_slice(&raw);
+                return Ok(key);
+            }
+            parse_hex_key(String::from_utf8_lossy(&raw).trim())
+        },
+        NetworkKeySource::Hex(hex_key) => parse_hex_key(hex_key.trim()),
+        NetworkKeySource::Passphrase(phrase) => {
+            if phrase.is_empty() { return Err("La frase de red está vacía".to_string()); }
+            let mut key = [0u8; 32];
+            Argon2::default()
+                .hash_password_into(phrase.as_bytes(), PASSPHRASE_SALT, &mut key)
+                .map_err(|e| format!("Fallo derivando la clave de red: {}", e))?;
+            Ok(key)
+        },
+        NetworkKeySource::Default => Ok(*DEFAULT_NETWORK_KEY),
+    }
+}
+
+fn parse_hex_key(hex_key: &str) -> Result<[u8; 32], String> {
+    let bytes = hex::decode(hex_key).map_err(|_| "La clave de red no es hex válido".to_string())?;
+    bytes.try_into().map_err(|_| "La clave de red debe tener 32 bytes".to_string())
+}
+
+/// Fija la clave de red del proceso (solo la primera llamada tiene efecto)
+pub fn set_network_key(key: [u8; 32]) {
+    let _ = NETWORK_KEY.set(key);
+}
+
+fn network_key() -> &'static [u8; 32] {
+    NETWORK_KEY.get().unwrap_or(DEFAULT_NETWORK_KEY)
+}
+
+/// Huella pública de la clave de red. Viaja en cada Header para descartar
+/// tramas de otras mallas sin intentar descifrarlas.
+pub fn network_id() -> [u8; 8] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"EMBER-NETWORK-ID-v1");
+    hasher.update(network_key());
+    let digest = hasher.finalize();
+    let mut id = [0u8; 8];
+    id.copy_from_slice(&digest[..8]);
+    id
+}
+
+/// Activa el relleno a tamaños fijos (modo privacidad)
+pub fn set_padding(enabled: bool) {
+    let _ = PADDING.set(enabled);
+}
+
+fn envelope(kind: u8, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(ENVELOPE_HEADER + data.len());
+    out.push(kind);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if PADDING.get().copied().unwrap_or(false) {
+        let target = BUCKETS.iter().copied().find(|b| *b >= out.len())
+            .unwrap_or_else(|| out.len().div_ceil(1024) * 1024);
+        out.resize(target, 0);
+    }
+    out
+}
+
+fn open_envelope(data: &[u8]) -> Option<(u8, &[u8])> {
+    if data.len() < ENVELOPE_HEADER { return None; }
+    let len = u32::from_le_bytes(data[1..ENVELOPE_HEADER].try_into().unwrap()) as usize;
+    let body = data.get(ENVELOPE_HEADER..ENVELOPE_HEADER.checked_add(len)?)?;
+    Some((data[0], body))
+}
+
+/// Mete los datos en el sobre (con relleno si está activo). Para los DMs, antes del trinquete.
+pub fn pad(plaintext: &[u8]) -> Vec<u8> {
+    envelope(ENVELOPE_DATA, plaintext)
+}
+
+/// Saca los datos del sobre. None si está mal formado o si era tráfico de cobertura.
+pub fn unpad(data: &[u8]) -> Option<Vec<u8>> {
+    match open_envelope(data)? {
+        (ENVELOPE_DATA, body) => Some(body.to_vec()),
+        _ => None,
+    }
+}
+
+/// Encripta con la clave de red (tráfico de difusión)
 pub fn encrypt(plaintext: &[u8]) -> Vec<u8> {
-    // 1. Iniciamos el cifrador con la clave maestra
-    let cipher = XChaCha20Poly1305::new(NETWORK_KEY.into());
+    encrypt_with(network_key(), &pad(plaintext))
+}
+
+/// Descifra con la clave de red (tráfico de difusión)
+pub fn decrypt(data: &[u8]) -> Option<Vec<u8>> {
+    unpad(&decrypt_with(network_key(), data)?)
+}
+
+/// 🎭 Contenido de una trama de cobertura: desde afuera es igual a un chat corto,
+/// pero quien lo descifra ve que es relleno y lo ignora (aunque lo reenvía igual).
+pub fn cover_payload() -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+    let size = BUCKETS[(rng.next_u32() % 2) as usize] - ENVELOPE_HEADER;
+    let mut filler = vec![0u8; (rng.next_u32() as usize) % size];
+    rng.fill_bytes(&mut filler);
+    encrypt_with(network_key(), &envelope(ENVELOPE_COVER, &filler))
+}
+
+/// Encripta los datos usando XChaCha20-Poly1305 con la llave indicada
+/// Devuelve: [NONCE (24 bytes) | CIPHERTEXT (datos cifrados)]
+pub fn encrypt_with(key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+    // 1. Iniciamos el cifrador con la llave recibida
+    let cipher = XChaCha20Poly1305::new(key.into());
 
     // 2. Generamos un "Nonce" (Número de uso único) aleatorio de 24 bytes
     // Esto es vital: asegura que si envías "Hola" dos veces, el cifrado se vea diferente.
@@ -29,12 +169,12 @@ pub fn encrypt(plaintext: &[u8]) -> Vec<u8> {
     // 4. Empaquetamos: Primero el Nonce (público), luego el contenido (secreto)
     let mut packet = nonce.to_vec();
     packet.extend_from_slice(&ciphertext);
-    
+
     packet
 }
 
-/// Intenta descifrar un paquete
-pub fn decrypt(data: &[u8]) -> Option<Vec<u8>> {
+/// Intenta descifrar un paquete con la llave indicada
+pub fn decrypt_with(key: &[u8; 32], data: &[u8]) -> Option<Vec<u8>> {
     // El paquete debe tener al menos 24 bytes (el tamaño del nonce)
     if data.len() < 24 {
         return None;
@@ -44,10 +184,22 @@ pub fn decrypt(data: &[u8]) -> Option<Vec<u8>> {
     let nonce_bytes = &data[..24];
     let ciphertext = &data[24..];
 
-    let cipher = XChaCha20Poly1305::new(NETWORK_KEY.into());
+    let cipher = XChaCha20Poly1305::new(key.into());
     let nonce = XNonce::from_slice(nonce_bytes);
 
     // 2. Intentamos descifrar
     // Esto fallará (devolverá None) si la clave es incorrecta O si el mensaje fue alterado.
     cipher.decrypt(nonce, ciphertext).ok()
-}
\ No newline at end of file
+}
+
+/// Deriva la llave de un par de nodos a partir del secreto X25519 compartido.
+/// Los IDs se ordenan para que ambos extremos obtengan la misma llave.
+pub fn derive_pair_key(shared_secret: &[u8; 32], a: &[u8; 8], b: &[u8; 8]) -> [u8; 32] {
+    let (low, high) = if a <= b { (a, b) } else { (b, a) };
+    let mut hasher = Sha256::new();
+    hasher.update(b"EMBER-DM-v1");
+    hasher.update(shared_secret);
+    hasher.update(low);
+    hasher.update(high);
+    hasher.finalize().into()
+}
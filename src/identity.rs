@@ -1,84 +1,260 @@
-use ed25519_dalek::{SigningKey, VerifyingKey, Signer};
+use crate::crypto;
+use argon2::{Algorithm, Argon2, Params, Version};
+use ed25519_dalek::{SigningKey, VerifyingKey};
 use rand::rngs::OsRng;
 use rand::RngCore; // 👈 Necesario para llenar los bytes aleatorios
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::Path;
+use x25519_dalek::{PublicKey, StaticSecret};
 
+const ENCRYPTED_VERSION: u8 = 2;
+const PROMPT_ATTEMPTS: usize = 3;
+
+// Formato viejo (v1): el secreto en claro. Solo se lee para migrarlo.
 #[derive(Serialize, Deserialize)]
 struct SavedIdentity {
-    secret_bytes: Vec<u8>, 
-    public_key_hex: String, 
+    secret_bytes: Vec<u8>,
+    public_key_hex: String,
+}
+
+/// 🔐 Secreto Ed25519 cifrado con una frase (Argon2id + XChaCha20-Poly1305)
+#[derive(Serialize, Deserialize)]
+pub struct EncryptedSecret {
+    version: u8,
+    kdf: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt_hex: String,
+    sealed_hex: String, // [NONCE | CIPHERTEXT] como lo produce crypto::encrypt_with
+    pub public_key_hex: String,
+}
+
+impl EncryptedSecret {
+    pub fn seal(signing: &SigningKey, passphrase: &str) -> Result<Self, String> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let params = Params::DEFAULT;
+        let key = derive_key(passphrase, &salt, params.m_cost(), params.t_cost(), params.p_cost())?;
+        Ok(Self {
+            version: ENCRYPTED_VERSION,
+            kdf: "argon2id".to_string(),
+            m_cost: params.m_cost(),
+            t_cost: params.t_cost(),
+            p_cost: params.p_cost(),
+            salt_hex: hex::encode(salt),
+            sealed_hex: hex::encode(crypto::encrypt_with(&key, &signing.to_bytes())),
+            public_key_hex: hex::encode(signing.verifying_key().to_bytes()),
+        })
+    }
+
+    pub fn open(&self, passphrase: &str) -> Result<SigningKey, String> {
+        if self.version != ENCRYPTED_VERSION || self.kdf != "argon2id" {
+            return Err(format!("Formato de identidad no soportado (v{}, {})", self.version, self.kdf));
+        }
+        let salt = hex::decode(&self.salt_hex).map_err(|_| "Sal corrupta")?;
+        let sealed = hex::decode(&self.sealed_hex).map_err(|_| "Secreto corrupto")?;
+        let key = derive_key(passphrase, &salt, self.m_cost, self.t_cost, self.p_cost)?;
+        let secret = crypto::decrypt_with(&key, &sealed).ok_or("Frase incorrecta")?;
+        let secret: [u8; 32] = secret.try_into().map_err(|_| "Secreto corrupto")?;
+        let signing = SigningKey::from_bytes(&secret);
+        // La llave pública guardada debe corresponder al secreto (los respaldos en papel no la traen)
+        if !self.public_key_hex.is_empty() && hex::encode(signing.verifying_key().to_bytes()) != self.public_key_hex {
+            return Err("La llave pública no coincide con el secreto".to_string());
+        }
+        Ok(signing)
+    }
+
+    /// Forma binaria compacta (para respaldos en papel):
+    /// [versión | m_cost | t_cost | p_cost | sal (16) | secreto sellado (72)]
+    pub fn to_compact(&self) -> Result<Vec<u8>, String> {
+        let salt = hex::decode(&self.salt_hex).map_err(|_| "Sal corrupta")?;
+        let sealed = hex::decode(&self.sealed_hex).map_err(|_| "Secreto corrupto")?;
+        let mut out = vec![self.version];
+        out.extend_from_slice(&self.m_cost.to_le_bytes());
+        out.extend_from_slice(&self.t_cost.to_le_bytes());
+        out.extend_from_slice(&self.p_cost.to_le_bytes());
+        out.extend_from_slice(&salt);
+        out.extend_from_slice(&sealed);
+        Ok(out)
+    }
+
+    /// Inverso de `to_compact`. La llave pública no viaja: se recupera al abrirlo.
+    pub fn from_compact(data: &[u8]) -> Result<Self, String> {
+        if data.len() != 1 + 12 + 16 + 72 || data[0] != ENCRYPTED_VERSION {
+            return Err("Respaldo con formato desconocido".to_string());
+        }
+        let word = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        Ok(Self {
+            version: data[0],
+            kdf: "argon2id".to_string(),
+            m_cost: word(1),
+            t_cost: word(5),
+            p_cost: word(9),
+            salt_hex: hex::encode(&data[13..29]),
+            sealed_hex: hex::encode(&data[29..]),
+            public_key_hex: String::new(),
+        })
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], m_cost: u32, t_cost: u32, p_cost: u32) -> Result<[u8; 32], String> {
+    let params = Params::new(m_cost, t_cost, p_cost, Some(32)).map_err(|e| format!("Parámetros Argon2 inválidos: {}", e))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Fallo derivando la llave: {}", e))?;
+    Ok(key)
+}
+
+/// Cómo obtener la frase que protege la identidad
+pub enum Unlock {
+    Passphrase(String), // Modo desatendido (EMBER_IDENTITY_PASSPHRASE o --identity-key-file)
+    Prompt,             // Se pregunta en la terminal
+}
+
+impl Unlock {
+    /// Frase para abrir (o crear, con `confirm`) un secreto cifrado
+    pub fn passphrase(&self, prompt: &str, confirm: bool) -> Result<String, String> {
+        match self {
+            Unlock::Passphrase(p) if p.is_empty() => Err("La frase de identidad está vacía".to_string()),
+            Unlock::Passphrase(p) => Ok(p.clone()),
+            Unlock::Prompt => loop {
+                let first = rpassword::prompt_password(format!("🔑 {}: ", prompt))
+                    .map_err(|_| "No hay terminal para pedir la frase (usa EMBER_IDENTITY_PASSPHRASE o --identity-key-file)")?;
+                if first.is_empty() { println!("⚠️ La frase no puede estar vacía."); continue; }
+                if !confirm { return Ok(first); }
+                let second = rpassword::prompt_password("🔑 Repite la frase: ").map_err(|e| e.to_string())?;
+                if first == second { return Ok(first); }
+                println!("⚠️ Las frases no coinciden.");
+            },
+        }
+    }
+
+    pub fn attempts(&self) -> usize {
+        match self { Unlock::Passphrase(_) => 1, Unlock::Prompt => PROMPT_ATTEMPTS }
+    }
 }
 
+#[derive(Clone)]
 pub struct Identity {
     pub signing: SigningKey,
     pub verify: VerifyingKey,
 }
 
 impl Identity {
-    pub fn load_or_generate(port: u16) -> Self {
-        let filename = format!("identity_{}.json", port);
-        let path = Path::new(&filename);
-
+    pub fn load_or_generate(path: &Path, unlock: &Unlock) -> Result<Self, String> {
         if path.exists() {
-            println!("📂 Cargando identidad existente desde {}...", filename);
-            match fs::read_to_string(path) {
-                Ok(json_content) => {
-                    if let Ok(saved) = serde_json::from_str::<SavedIdentity>(&json_content) {
-                        let array_bytes: [u8; 32] = match saved.secret_bytes.try_into() {
-                            Ok(arr) => arr,
-                            Err(_) => {
-                                println!("⚠️ Archivo corrupto. Generando nueva.");
-                                return Self::generate_and_save(&filename);
-                            }
-                        };
-                        let signing = SigningKey::from_bytes(&array_bytes);
-                        let verify = signing.verifying_key();
-                        return Self { signing, verify };
-                    }
-                },
-                Err(_) => println!("⚠️ Error leyendo archivo."),
-            }
+            println!("📂 Cargando identidad existente desde {}...", path.display());
+            return Self::load(path, unlock);
         }
 
         println!("🆕 Creando nueva identidad...");
-        Self::generate_and_save(&filename)
+        Self::generate_and_save(path, unlock)
     }
 
-    fn generate_and_save(filename: &str) -> Self {
-        // 🛠️ ARREGLO DEL ERROR:
-        // En lugar de SigningKey::generate, hacemos esto:
-        
-        // 1. Generamos 32 bytes de ruido aleatorio puro
+    pub fn generate() -> Self {
+        // 32 bytes de ruido aleatorio puro como secreto
         let mut secret_bytes_arr = [0u8; 32];
         OsRng.fill_bytes(&mut secret_bytes_arr);
+        Self::from_signing(SigningKey::from_bytes(&secret_bytes_arr))
+    }
 
-        // 2. Creamos la llave usando esos bytes (esto es lo que pedía el compilador: from_bytes)
-        let signing = SigningKey::from_bytes(&secret_bytes_arr);
-        let verify = signing.verifying_key();
+    /// Abre un archivo de identidad (migrando el formato viejo en texto plano)
+    pub fn load(path: &Path, unlock: &Unlock) -> Result<Self, String> {
+        let json_content = fs::read_to_string(path).map_err(|e| format!("Error leyendo {}: {}", path.display(), e))?;
 
-        // Guardamos
-        let secret_bytes = secret_bytes_arr.to_vec();
-        let public_key_hex = hex::encode(verify.to_bytes());
+        if let Ok(sealed) = serde_json::from_str::<EncryptedSecret>(&json_content) {
+            return Self::unseal(&sealed, unlock, "Frase de la identidad");
+        }
 
-        let saved = SavedIdentity {
-            secret_bytes,
-            public_key_hex,
-        };
+        // 🔄 Migración: identidad vieja en texto plano -> se vuelve a guardar cifrada
+        if let Ok(saved) = serde_json::from_str::<SavedIdentity>(&json_content) {
+            let array_bytes: [u8; 32] = match saved.secret_bytes.try_into() {
+                Ok(arr) => arr,
+                Err(_) => {
+                    println!("⚠️ Archivo corrupto. Generando nueva.");
+                    return Self::generate_and_save(path, unlock);
+                }
+            };
+            println!("🔐 La identidad está en texto plano: se cifrará con una frase.");
+            let identity = Self::from_signing(SigningKey::from_bytes(&array_bytes));
+            identity.save(path, unlock)?;
+            return Ok(identity);
+        }
 
-        if let Ok(json) = serde_json::to_string_pretty(&saved) {
-            let _ = fs::write(filename, json);
-            println!("💾 Identidad guardada en {}", filename);
+        Err(format!("{} no es un archivo de identidad válido", path.display()))
+    }
+
+    /// Descifra un secreto (archivo o respaldo), con reintentos si se pregunta en la terminal
+    pub fn unseal(sealed: &EncryptedSecret, unlock: &Unlock, prompt: &str) -> Result<Self, String> {
+        let mut last_error = String::new();
+        for _ in 0..unlock.attempts() {
+            let passphrase = unlock.passphrase(prompt, false)?;
+            match sealed.open(&passphrase) {
+                Ok(signing) => return Ok(Self::from_signing(signing)),
+                Err(e) => { println!("⛔ {}", e); last_error = e; }
+            }
         }
+        Err(last_error)
+    }
+
+    /// Llave pública guardada en un archivo de identidad (no hace falta la frase)
+    pub fn read_public_key(path: &Path) -> Result<[u8; 32], String> {
+        let json_content = fs::read_to_string(path).map_err(|e| format!("Error leyendo {}: {}", path.display(), e))?;
+        let public_key_hex = serde_json::from_str::<EncryptedSecret>(&json_content).map(|s| s.public_key_hex)
+            .or_else(|_| serde_json::from_str::<SavedIdentity>(&json_content).map(|s| s.public_key_hex))
+            .map_err(|_| format!("{} no es un archivo de identidad válido", path.display()))?;
+        hex::decode(public_key_hex).ok().and_then(|b| b.try_into().ok()).ok_or("Llave pública corrupta".to_string())
+    }
+
+    fn generate_and_save(path: &Path, unlock: &Unlock) -> Result<Self, String> {
+        let identity = Self::generate();
+        identity.save(path, unlock)?;
+        Ok(identity)
+    }
+
+    /// Guarda la identidad cifrada con la frase que entregue `unlock`
+    pub fn save(&self, path: &Path, unlock: &Unlock) -> Result<(), String> {
+        let passphrase = unlock.passphrase("Nueva frase para cifrar la identidad", true)?;
+        let sealed = EncryptedSecret::seal(&self.signing, &passphrase)?;
+        let json = serde_json::to_string_pretty(&sealed).map_err(|e| e.to_string())?;
+        fs::write(path, json).map_err(|e| format!("No se pudo guardar {}: {}", path.display(), e))?;
+        println!("💾 Identidad cifrada guardada en {}", path.display());
+        Ok(())
+    }
 
+    fn from_signing(signing: SigningKey) -> Self {
+        let verify = signing.verifying_key();
         Self { signing, verify }
     }
 
     pub fn node_id(&self) -> [u8; 8] {
-        let pub_bytes = self.verify.to_bytes();
-        let mut id = [0u8; 8];
-        id.copy_from_slice(&pub_bytes[0..8]);
-        id
+        node_id_from_pubkey(&self.verify.to_bytes())
     }
-}
\ No newline at end of file
+
+    /// Llave X25519 para acordar llaves de sesión (derivada del mismo secreto Ed25519)
+    pub fn x25519_secret(&self) -> StaticSecret {
+        StaticSecret::from(self.signing.to_scalar_bytes())
+    }
+
+    pub fn x25519_public(&self) -> [u8; 32] {
+        PublicKey::from(&self.x25519_secret()).to_bytes()
+    }
+}
+
+/// El ID de un nodo son los primeros 8 bytes de su llave pública Ed25519
+pub fn node_id_from_pubkey(pub_bytes: &[u8; 32]) -> [u8; 8] {
+    let mut id = [0u8; 8];
+    id.copy_from_slice(&pub_bytes[0..8]);
+    id
+}
+
+/// Huella legible de una llave pública, para comparar a ojo o por radio
+pub fn fingerprint(pub_bytes: &[u8; 32]) -> String {
+    let digest = Sha256::digest(pub_bytes);
+    let hex_digest = hex::encode_upper(&digest[..16]);
+    hex_digest.as_bytes().chunks(4).map(|c| String::from_utf8_lossy(c).into_owned()).collect::<Vec<_>>().join("-")
+}
@@ -6,12 +6,30 @@ mod rate_limiter;
 mod node;
 mod crypto;
 mod chunker;
+mod config;
+mod trust;
+mod session;
+mod backup;
+mod profile;
+mod revocation;
+mod pow;
+mod blocklist;
+mod wire;
+mod channels;
+mod routing;
+mod neighbor;
+mod peer_exchange;
+mod address_book;
 
 use identity::Identity;
-use protocol::{Frame, Header, MessageType, MAGIC_BYTES, CURRENT_VERSION, BROADCAST_ID};
+use protocol::{Frame, Header, Hello, KeyUpdate, LinkProbe, MessageType, PeerList, MAGIC_BYTES, CURRENT_VERSION, FLOOD_TTL, MAX_TTL, BROADCAST_ID, FLAG_WANT_HELLO};
 use transport::Transport;
-use node::Node;
+use node::{Node, NodeOptions, NodeStores};
+use routing::{RouteError, RouteReply, RouteRequest};
 use chunker::Assembler;
+use channels::ChannelInvite;
+use config::Config;
+use profile::Profile;
 
 use std::env;
 use std::fs;
@@ -20,9 +38,9 @@ use std::net::SocketAddr;
 use std::path::Path;
 use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
-use std::time::{Duration};
+use std::time::{Duration, Instant};
 use ed25519_dalek::Signer;
-use rand::RngCore;
+use rand::{Rng, RngCore};
 
 // --- Librerías de Interfaz (TUI) ---
 use crossterm::{
@@ -45,23 +63,47 @@ struct App {
     input: String,
     node_id_hex: String,
     port: u16,
+    profile_name: String,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 { 
-        println!("Uso: cargo run <MI_PUERTO> [IP_VECINO:PUERTO]");
+    if args.get(1).map(String::as_str) == Some("identity") {
+        return Ok(backup::run(&args[2..])?);
+    }
+    if args.get(1).map(String::as_str) == Some("profiles") {
+        let data_dir_flag = args.iter().position(|a| a == "--data-dir").and_then(|i| args.get(i + 1));
+        let data_dir = Profile::data_dir(data_dir_flag.map(Into::into));
+        println!("🗂️ Perfiles en {}:", data_dir.display());
+        for name in Profile::list(&data_dir) { println!("  - {}", name); }
         return Ok(());
     }
-    
-    let port: u16 = args[1].parse().expect("Puerto inválido");
-    let initial_peer: Option<SocketAddr> = if args.len() > 2 {
-        let peer_addr_str = &args[2];
-        Some(peer_addr_str.parse().expect("Dirección inválida"))
-    } else { None };
+    let config = match Config::from_args(&args) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("❌ {}\n{}", e, config::USAGE);
+            return Ok(());
+        }
+    };
+    let port = config.port;
+    let initial_peer = config.initial_peer;
 
     // --- Configuración Inicial ---
-    let id = Identity::load_or_generate(port);
+    // 🔑 La clave de red se fija antes de construir o aceptar cualquier trama
+    let net_key = crypto::load_network_key(&config.net_key)?;
+    crypto::set_network_key(net_key);
+    crypto::set_padding(config.privacy);
+    wire::set_sealed_headers(config.seal_headers);
+    if matches!(config.net_key, crypto::NetworkKeySource::Default) {
+        println!("⚠️ Usando la clave de red de fábrica: cualquiera con el binario puede leer la difusión.");
+    }
+    if args.iter().any(|a| a == "--net-passphrase") {
+        println!("⚠️ La frase de --net-passphrase queda a la vista de otros usuarios en `ps`: mejor EMBER_NET_PASSPHRASE o --net-key-file.");
+    }
+    let profile = &config.profile;
+    if let Some(msg) = profile.adopt_legacy_files(port) { println!("{}", msg); }
+    println!("🗂️ Perfil '{}' ({})", profile.name, profile.dir.display());
+    let id = Identity::load_or_generate(&profile.identity_path(), &config.identity_unlock)?;
     let node_id = id.node_id();
     let pubkey_bytes = id.verify.to_bytes();
     let node_id_hex = hex::encode(node_id);
@@ -73,72 +115,231 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let t_hb = transport.try_clone();
     let t_main = transport.try_clone();
 
-    let id_hb = Identity { signing: ed25519_dalek::SigningKey::from_bytes(&id.signing.to_bytes()), verify: id.verify.clone() };
-    let node_id_hb = node_id;
-    let pubkey_hb = pubkey_bytes;
-    let id_ack = Identity { signing: ed25519_dalek::SigningKey::from_bytes(&id.signing.to_bytes()), verify: id.verify.clone() };
+    let id_hb = id.clone();
+    let id_ack = id.clone();
 
-    let node = Arc::new(Mutex::new(Node::new(node_id)));
+    let stores = match NodeStores::load(profile) {
+        Ok(stores) => stores,
+        Err(e) => {
+            println!("❌ {}", e);
+            return Ok(());
+        }
+    };
+    if stores.revoked.is_revoked(&pubkey_bytes) {
+        println!("🚨 Esta identidad fue REVOCADA: la malla descartará sus tramas. Usa `identity rotate`.");
+    }
+    if config.pow_bits > 0 {
+        pow::set_stamp(pow::load_or_mine(&profile.pow_path(), &pubkey_bytes, config.pow_bits));
+    }
+    let options = NodeOptions { pow_bits: config.pow_bits, relay_blocked: config.relay_blocked, on_demand: config.on_demand };
+    let node = Arc::new(Mutex::new(Node::new(node_id, id.x25519_secret(), stores, options)));
 
     if let Some(peer) = initial_peer {
-        let mut n = node.lock().unwrap(); n.add_peer(peer); drop(n); 
-        let enc = crypto::encrypt(b"");
-        let frame = build_frame(&id, node_id, pubkey_bytes, BROADCAST_ID, MessageType::Hello, enc);
-        transport.send(&bincode::serialize(&frame).unwrap(), peer);
+        let mut n = node.lock().unwrap(); n.add_peer(peer);
+        let updates = key_update_packets(&id, &n);
+        let probe = build_link_probe(&id, n.probe_echoes());
+        drop(n);
+        transport.send(&wire::encode(&probe), peer);
+        transport.send(&wire::encode(&build_hello(&id, BROADCAST_ID, 0, FLOOD_TTL)), peer);
+        for pkt in updates { transport.send(&pkt, peer); }
+    }
+
+    // 📒 Volvemos a buscar a los del archivo de configuración y a los vecinos de la última vez
+    {
+        let mut n = node.lock().unwrap();
+        let known = n.address_book.bootstrap();
+        for addr in config.bootstrap.iter().chain(&known).filter(|a| Some(**a) != initial_peer) {
+            n.candidates.add(*addr);
+        }
+        let probes = n.candidates.probe();
+        let frame = build_link_probe(&id, n.probe_echoes());
+        drop(n);
+        if !probes.is_empty() { println!("📒 Buscando a {} vecinos conocidos...", probes.len()); }
+        let pkt = wire::encode(&frame);
+        for addr in probes { transport.send(&pkt, addr); }
     }
 
     let (tx, rx) = mpsc::channel::<String>();
 
     // 1. Hilo de Mantenimiento
+    let discovery = config.discovery;
     let node_hb = node.clone();
     let tx_hb = tx.clone();
     thread::spawn(move || {
-        loop {
-            thread::sleep(Duration::from_secs(5));
+        for tick in 0u64.. {
+            thread::sleep(neighbor::PROBE_INTERVAL);
             let mut n = node_hb.lock().unwrap();
             let dead = n.prune_dead_nodes(Duration::from_secs(15));
+            n.save_replay_state();
+            if tick % 6 == 0 { n.save_address_book(); }
             if !dead.is_empty() { 
                 for d in dead { let _ = tx_hb.send(format!("💀 Timeout: {}", d)); }
             }
             let peers: Vec<SocketAddr> = n.peers.keys().cloned().collect();
+            // 🪦 Cada minuto les repetimos a los vecinos (TTL 1) las revocaciones recientes: de vecino
+            // en vecino llegan a los que se conectaron tarde, sin reinundar la malla entera
+            let mut updates = if tick % 12 == 0 { key_update_packets(&id_hb, &n) } else { Vec::new() };
+            let broken = n.routes.take_errors();
+            if n.routes.is_on_demand() {
+                // 💔 Bajo demanda no hay anuncios: las caídas se avisan con un RERR a los vecinos
+                if !broken.is_empty() { updates.push(wire::encode(&build_route_error(&id_hb, broken))); }
+                for (dest, dropped) in n.routes.expire_pending() {
+                    let _ = tx_hb.send(format!("⌛ Sin ruta a {}: {} tramas descartadas", hex::encode(dest), dropped));
+                }
+            } else if tick % (routing::ADVERT_INTERVAL.as_secs() / neighbor::PROBE_INTERVAL.as_secs()) == 0 {
+                // 🧭 Cada ADVERT_INTERVAL le contamos a los vecinos (y solo a ellos) nuestras rutas
+                let enc = crypto::encrypt(&bincode::serialize(&n.routes.advert()).unwrap());
+                updates.push(wire::encode(&build_frame(&id_hb, id_hb.node_id(), id_hb.verify.to_bytes(), BROADCAST_ID, MessageType::RouteAdvert, enc, 1)));
+            }
+            // 🤝 Cada PEER_LIST_INTERVAL les presentamos a los vecinos los nuestros
+            if tick % (peer_exchange::PEER_LIST_INTERVAL.as_secs() / neighbor::PROBE_INTERVAL.as_secs()) == 0 {
+                updates.push(wire::encode(&build_peer_list(&id_hb, &n.peer_list())));
+            }
+            // 📡 Beacon a la LAN (sale de nuestro socket: quien lo oye ya sabe a qué puerto contestar)
+            let beacon = (discovery && tick % (transport::BEACON_INTERVAL.as_secs() / neighbor::PROBE_INTERVAL.as_secs()) == 0)
+                .then(|| wire::encode(&build_frame(&id_hb, id_hb.node_id(), id_hb.verify.to_bytes(), BROADCAST_ID, MessageType::Beacon, crypto::encrypt(&[]), 1)));
+            // 🔑 La llave de DM se inunda cada HELLO_INTERVAL (los vecinos nuevos la reciben al llegar)
+            if tick % (protocol::HELLO_INTERVAL.as_secs() / neighbor::PROBE_INTERVAL.as_secs()) == 0 {
+                updates.push(wire::encode(&build_hello(&id_hb, BROADCAST_ID, 0, FLOOD_TTL)));
+            }
+            // 📮 Cada 30 s reenviamos los estados de canal que ningún ACK confirmó todavía
+            let owed: Vec<(Frame, Vec<SocketAddr>)> = if tick % 6 == 0 {
+                n.channels.owed_invites(&id_hb).into_iter()
+                    .filter_map(|(member, invite)| channel_state_frame(&mut n, &id_hb, member, &invite).1)
+                    .collect()
+            } else { Vec::new() };
+            let probes = n.candidates.probe();
+            let frame = build_link_probe(&id_hb, n.probe_echoes());
             drop(n);
-            if !peers.is_empty() {
-                let enc = crypto::encrypt(b"");
-                let frame = build_frame(&id_hb, node_id_hb, pubkey_hb, BROADCAST_ID, MessageType::Hello, enc);
-                let pkt = bincode::serialize(&frame).unwrap();
-                for peer in peers { t_hb.send(&pkt, peer); }
+            for (frame, targets) in &owed {
+                let packet = wire::encode(frame);
+                for peer in targets { t_hb.send(&packet, *peer); }
+            }
+            let pkt = wire::encode(&frame);
+            for peer in &peers {
+                t_hb.send(&pkt, *peer);
+                for update in &updates { t_hb.send(update, *peer); }
+            }
+            // Los candidatos solo reciben un sondeo: si responden, pasan a ser vecinos
+            for candidate in probes { t_hb.send(&pkt, candidate); }
+            if let Some(beacon) = beacon {
+                for group in Transport::discovery_targets() { t_hb.send_multicast(&beacon, group); }
             }
         }
     });
 
+    // 🎭 Hilo de cobertura (opcional): chats falsos a intervalos irregulares
+    if let Some(secs) = config.cover_traffic {
+        let node_cv = node.clone();
+        let id_cv = id.clone();
+        let t_cv = transport.try_clone();
+        thread::spawn(move || {
+            loop {
+                let jitter: f64 = rand::thread_rng().gen_range(0.5..1.5);
+                thread::sleep(Duration::from_secs_f64(secs as f64 * jitter));
+                let peers: Vec<SocketAddr> = node_cv.lock().unwrap().peers.keys().cloned().collect();
+                let frame = build_frame(&id_cv, id_cv.node_id(), id_cv.verify.to_bytes(), BROADCAST_ID, MessageType::Chat, crypto::cover_payload(), FLOOD_TTL);
+                let pkt = wire::encode(&frame);
+                for peer in peers { t_cv.send(&pkt, peer); }
+            }
+        });
+    }
+
+    // 📡 Hilos de descubrimiento: lo que llega al grupo multicast pasa al receptor
+    let (beacon_tx, beacon_rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>();
+    if config.discovery {
+        for t_disc in Transport::join_discovery() {
+            let beacon_tx = beacon_tx.clone();
+            thread::spawn(move || {
+                loop {
+                    if let Some(packet) = t_disc.recv() && beacon_tx.send(packet).is_err() { break; }
+                }
+            });
+        }
+    }
+
     // 2. Hilo Receptor
     let node_clone = node.clone();
     let tx_net = tx.clone();
     thread::spawn(move || {
         loop {
-            if let Some((data, src)) = t_lis.recv() {
-                if let Ok(frame) = bincode::deserialize::<Frame>(&data) {
-                    let mut n = node_clone.lock().unwrap();
-                    let res = n.on_frame(frame, src); 
-                    
-                    if let Some(log_msg) = res.log_output {
-                         let _ = tx_net.send(log_msg);
-                    }
-
-                    let peers: Vec<SocketAddr> = n.peers.keys().cloned().collect();
-                    drop(n);
+            if let Some((data, src)) = t_lis.recv().or_else(|| beacon_rx.try_recv().ok())
+                && let Some(decoded) = wire::decode(&data) {
+                let mut n = node_clone.lock().unwrap();
+                let frame = match decoded {
+                    wire::Decoded::Frame(frame) => frame,
+                    wire::Decoded::ForeignMesh(network_id) => {
+                        if let Some(log_msg) = n.on_foreign_mesh(src, network_id) { let _ = tx_net.send(log_msg); }
+                        continue;
+                    },
+                };
+                let res = n.on_frame(frame, src); 
+                
+                if let Some(log_msg) = res.log_output {
+                     let _ = tx_net.send(log_msg);
+                }
 
-                    if let Some(relay) = res.frame_to_relay {
-                        let pkt = bincode::serialize(&relay).unwrap();
-                        for peer in peers { if peer != src { t_relay.send(&pkt, peer); } }
-                    }
-                    if let Some((target, msg_id)) = res.ack_to_send {
-                        let py = bincode::serialize(&msg_id).unwrap();
-                        let enc = crypto::encrypt(&py);
-                        let af = build_frame(&id_ack, node_id, pubkey_bytes, BROADCAST_ID, MessageType::Ack, enc);
-                        t_ack.send(&bincode::serialize(&af).unwrap(), target);
+                let peers: Vec<SocketAddr> = n.peers.keys().cloned().collect();
+                // ✅ El ACK vuelve por DM al emisor original, por su ruta si la conocemos
+                let ack = res.ack_to_send.and_then(|(dest, msg_id)| {
+                    let enc = n.seal_dm(dest, &bincode::serialize(&msg_id).unwrap())?;
+                    let (targets, ttl) = route_to(&n, dest);
+                    dispatch(&mut n, &id_ack, build_frame(&id_ack, node_id, pubkey_bytes, dest, MessageType::Ack, enc, ttl), targets)
+                });
+                // 📬 Nos buscaban: respondemos por la ruta de vuelta que dejó el RREQ
+                let reply = res.route_reply_to.map(|origin| {
+                    let enc = crypto::encrypt(&bincode::serialize(&RouteReply { seq: n.routes.next_seq() }).unwrap());
+                    let (targets, ttl) = route_to(&n, origin);
+                    (build_frame(&id_ack, node_id, pubkey_bytes, origin, MessageType::RouteReply, enc, ttl), targets)
+                });
+                // 🔑 Nos pidieron la llave de DM: el Hello va por la ruta hacia quien la pidió
+                let hello = res.hello_to.and_then(|dest| {
+                    let (targets, ttl) = route_to(&n, dest);
+                    dispatch(&mut n, &id_ack, build_hello(&id_ack, dest, 0, ttl), targets)
+                });
+                // 📮 Los estados de canal que le debíamos a un miembro sin llave
+                let invites: Vec<(Frame, Vec<SocketAddr>)> = res.invites_to.map(|member| {
+                    n.channels.owed_invites(&id_ack).into_iter().filter(|(m, _)| *m == member).filter_map(|(_, invite)| {
+                        let (sealed, frame) = channel_state_frame(&mut n, &id_ack, member, &invite);
+                        if sealed { let _ = tx_net.send(format!("📮 {} (época {}) enviado a {}", invite.name, invite.epoch, hex::encode(member))); }
+                        frame
+                    }).collect()
+                }).unwrap_or_default();
+                // Lo que esperaba una ruta y ya la tiene
+                let ready = n.routes.take_ready();
+                // 💔 Un RERR nos rompió rutas: avisamos a nuestros vecinos (y ellos, si les rompe algo, a los suyos)
+                let broken = n.routes.take_errors();
+                let route_error = (n.routes.is_on_demand() && !broken.is_empty()).then(|| wire::encode(&build_route_error(&id_ack, broken)));
+                // 👋 Vecino nuevo: el sondeo va primero (con su eco, nos confirma) y así acepta la lista
+                let welcome = res.peer_list_to.map(|addr| {
+                    let mut packets = vec![
+                        wire::encode(&build_link_probe(&id_ack, n.probe_echoes())),
+                        wire::encode(&build_hello(&id_ack, BROADCAST_ID, 0, 1)),
+                        wire::encode(&build_peer_list(&id_ack, &n.peer_list())),
+                    ];
+                    packets.extend(key_update_packets(&id_ack, &n));
+                    (packets, addr)
+                });
+                drop(n);
+
+                if let Some(relay) = res.frame_to_relay {
+                    let pkt = wire::encode(&relay);
+                    match res.relay_to {
+                        Some(next_hop) => t_relay.send(&pkt, next_hop),
+                        None => for peer in &peers { if *peer != src { t_relay.send(&pkt, *peer); } },
                     }
                 }
+                for (frame, targets) in ack.into_iter().chain(reply).chain(hello).chain(invites) {
+                    let pkt = wire::encode(&frame);
+                    for target in targets { t_ack.send(&pkt, target); }
+                }
+                for (next_hop, frame) in ready { t_ack.send(&wire::encode(&frame), next_hop); }
+                if let Some(pkt) = route_error {
+                    for peer in peers.iter().filter(|p| **p != src) { t_ack.send(&pkt, *peer); }
+                }
+                if let Some((packets, addr)) = welcome {
+                    for pkt in packets { t_ack.send(&pkt, addr); }
+                }
             }
         }
     });
@@ -160,9 +361,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         input: String::new(),
         node_id_hex: node_id_hex.clone(),
         port,
+        profile_name: profile.name.clone(),
     };
 
-    let res = run_app(&mut terminal, app, rx, node, id, node_id, pubkey_bytes, t_main);
+    let res = run_app(&mut terminal, app, rx, node.clone(), id, t_main);
+    node.lock().unwrap().save_replay_state();
+    node.lock().unwrap().save_address_book();
 
     disable_raw_mode()?;
     execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableMouseCapture)?;
@@ -181,8 +385,6 @@ fn run_app(
     rx: mpsc::Receiver<String>,
     node: Arc<Mutex<Node>>,
     id: Identity,
-    my_node_id: [u8; 8],
-    my_pubkey: [u8; 32],
     transport: Transport,
 ) -> io::Result<()> {
     
@@ -204,7 +406,7 @@ fn run_app(
                 ].as_ref())
                 .split(f.area());
 
-            let title_text = format!(" EMBER MESH | PORT: {} | ID: {} ", app.port, &app.node_id_hex[0..8]);
+            let title_text = format!(" EMBER MESH | PERFIL: {} | PORT: {} | ID: {} ", app.profile_name, app.port, &app.node_id_hex[0..8]);
             let title = Paragraph::new(title_text)
                 .style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
                 .block(Block::default()
@@ -217,6 +419,8 @@ fn run_app(
                 .map(|m| {
                     let style = if m.starts_with(">") {
                         Style::default().fg(Color::Yellow)
+                    } else if m.contains("ALERTA") {
+                        Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD)
                     } else if m.contains("TIMEOUT") || m.contains("Error") {
                         Style::default().fg(Color::Red)
                     } else if m.contains("ARCHIVO") {
@@ -252,20 +456,19 @@ fn run_app(
             app.messages.insert(0, msg);
         }
 
-        if event::poll(Duration::from_millis(100))? {
-            if let Event::Key(key) = event::read()? {
-                match key.code {
-                    KeyCode::Esc => return Ok(()),
-                    KeyCode::Enter => {
-                        let input_text: String = app.input.drain(..).collect();
-                        if !input_text.is_empty() {
-                            process_command(&input_text, &mut app, &node, &id, my_node_id, my_pubkey, &transport);
-                        }
-                    },
-                    KeyCode::Char(c) => { app.input.push(c); },
-                    KeyCode::Backspace => { app.input.pop(); },
-                    _ => {}
-                }
+        if event::poll(Duration::from_millis(100))?
+            && let Event::Key(key) = event::read()? {
+            match key.code {
+                KeyCode::Esc => return Ok(()),
+                KeyCode::Enter => {
+                    let input_text: String = app.input.drain(..).collect();
+                    if !input_text.is_empty() {
+                        process_command(&input_text, &mut app, &node, &id, &transport);
+                    }
+                },
+                KeyCode::Char(c) => { app.input.push(c); },
+                KeyCode::Backspace => { app.input.pop(); },
+                _ => {}
             }
         }
     }
@@ -276,36 +479,106 @@ fn process_command(
     app: &mut App, 
     node: &Arc<Mutex<Node>>, 
     id: &Identity, 
-    node_id: [u8; 8], 
-    pubkey: [u8; 32],
     transport: &Transport
 ) {
     app.messages.insert(0, format!("> {}", text));
+    let node_id = id.node_id();
+    let pubkey = id.verify.to_bytes();
 
     let mut dest_id = BROADCAST_ID;
     let mut data_to_send = Vec::new();
 
     if text == "/help" {
-        app.messages.insert(0, "CMD: /dm <ID> <msg>, /send <file>, /status".to_string());
+        app.messages.insert(0, "CMD: /dm <ID> <msg>, /send <file>, /status, /verify <ID> [ok|no], /announce <file>, /block|/unblock|/mute|/unmute <ID>, /join|/create|/leave #canal, /ch #canal <msg>, /invite|/kick #canal <ID>, /members #canal, /routes".to_string());
         return;
     }
     
     if text == "/status" {
         let n = node.lock().unwrap();
-        app.messages.insert(0, format!("📊 VECINOS ACTIVOS: {:?}", n.peers.keys()));
+        app.messages.insert(0, format!("📊 VECINOS ACTIVOS: {} ({} candidatos por probar, {} en la libreta)", n.peers.len(), n.candidates.len(), n.address_book.len()));
+        let now = Instant::now();
+        for (addr, neighbor) in &n.peers {
+            let id = neighbor.node_id.map(hex::encode).unwrap_or_else(|| "?".to_string());
+            let rtt = neighbor.rtt.map_or("-".to_string(), |rtt| format!("{}ms", rtt.as_millis()));
+            let percent = |ratio: Option<f64>| ratio.map_or("-".to_string(), |r| format!("{:.0}%", r * 100.0));
+            let etx = neighbor.etx(now).map_or("-".to_string(), |etx| format!("{:.2}", etx));
+            app.messages.insert(0, format!("   📶 {} [{}] RTT {} · entrega ida {} / vuelta {} · ETX {}",
+                addr, id, rtt, percent(neighbor.forward_delivery()), percent(neighbor.reverse_delivery(now)), etx));
+        }
+        for (addr, remaining) in n.bans() {
+            app.messages.insert(0, format!("🚫 BANEADO: {} ({}s restantes)", addr, remaining.as_secs()));
+        }
+        let blocked: Vec<&String> = n.blocklist.blocked().collect();
+        let muted: Vec<&String> = n.blocklist.muted().collect();
+        if !blocked.is_empty() { app.messages.insert(0, format!("🚫 BLOQUEADOS: {:?}", blocked)); }
+        if !muted.is_empty() { app.messages.insert(0, format!("🔇 SILENCIADOS: {:?}", muted)); }
+        let joined: Vec<&String> = n.channels.joined().collect();
+        if !joined.is_empty() { app.messages.insert(0, format!("📢 CANALES: {:?}", joined)); }
+        return;
+    }
+
+    if text == "/routes" {
+        let n = node.lock().unwrap();
+        let mut routes: Vec<_> = n.routes.routes().collect();
+        routes.sort_by_key(|(_, r)| r.metric);
+        app.messages.insert(0, format!("🧭 RUTAS ({}):", routes.len()));
+        for (dest, route) in routes {
+            app.messages.insert(0, format!("   {} via {} ({} saltos, seq {})", hex::encode(dest), route.next_hop, route.metric, route.seq));
+        }
+        return;
+    }
+
+    if let Some(rest) = text.strip_prefix("/verify ") {
+        verify_command(rest, app, node, id);
+        return;
+    }
+
+    for command in ["/block", "/unblock", "/mute", "/unmute"] {
+        if let Some(rest) = text.strip_prefix(command).and_then(|r| r.strip_prefix(' ')) {
+            block_command(command, rest.trim(), app, node);
+            return;
+        }
+    }
+
+    for command in ["/join", "/create", "/leave", "/ch", "/invite", "/kick", "/members"] {
+        if let Some(rest) = text.strip_prefix(command).and_then(|r| r.strip_prefix(' ')) {
+            channel_command(command, rest.trim(), app, node, id, transport);
+            return;
+        }
+    }
+
+    if let Some(path) = text.strip_prefix("/announce ") {
+        announce_command(path.trim(), app, node, id, transport);
         return;
     }
 
     if text.starts_with("/dm ") {
         let parts: Vec<&str> = text.splitn(3, ' ').collect();
         if parts.len() < 3 { return; }
-        if let Ok(bytes) = hex::decode(parts[1]) {
-            if bytes.len() <= 8 {
-                let mut full_id = [0u8; 8];
-                for (i, b) in bytes.iter().enumerate() { if i < 8 { full_id[i] = *b; } }
-                dest_id = full_id;
-                data_to_send = parts[2].as_bytes().to_vec();
+        if let Ok(bytes) = hex::decode(parts[1])
+            && bytes.len() <= 8 {
+            // Aceptamos IDs parciales si coinciden con un único nodo conocido (el ID completo, siempre)
+            let mut n = node.lock().unwrap();
+            match n.resolve_node_id(&bytes).or_else(|| <[u8; 8]>::try_from(bytes.as_slice()).ok()) {
+                Some(full_id) => dest_id = full_id,
+                None => {
+                    app.messages.insert(0, format!("❌ ERROR: Nodo {} desconocido (usa su ID completo)", parts[1]));
+                    return;
+                }
             }
+            // 🔑 Sin su llave de DM (está más lejos que la inundación de los Hello): se la pedimos
+            if !n.has_dm_key(&dest_id) {
+                let (targets, ttl) = route_to(&n, dest_id);
+                let request = dispatch(&mut n, id, build_hello(id, dest_id, FLAG_WANT_HELLO, ttl), targets);
+                drop(n);
+                if let Some((frame, targets)) = request {
+                    let packet = wire::encode(&frame);
+                    for peer in &targets { transport.send(&packet, *peer); }
+                }
+                app.messages.insert(0, format!("🔑 Todavía no tenemos la llave de DM de {}: se la pedimos, reintenta cuando llegue", hex::encode(dest_id)));
+                return;
+            }
+            data_to_send = parts[2].as_bytes().to_vec();
         }
     } else if text.starts_with("/send ") {
         let path_str = text.replace("/send ", "");
@@ -324,8 +597,24 @@ fn process_command(
         data_to_send = text.as_bytes().to_vec();
     }
 
-    let peers: Vec<SocketAddr> = { let n = node.lock().unwrap(); n.peers.keys().cloned().collect() };
-    
+    let (peers, ttl) = route_to(&node.lock().unwrap(), dest_id);
+    // 🔐 Los DMs van por la sesión de trinquete con el destinatario
+    let seal = |data: &[u8]| -> Option<Vec<u8>> {
+        if dest_id == BROADCAST_ID { Some(crypto::encrypt(data)) } else { node.lock().unwrap().seal_dm(dest_id, data) }
+    };
+    // 🔎 Sin ruta (bajo demanda) la trama espera y sale una búsqueda en su lugar
+    let send = |frame: Frame, app: &mut App| {
+        let mut n = node.lock().unwrap();
+        if dest_id != BROADCAST_ID { n.expect_ack(frame.header.msg_id); }
+        let Some((frame, targets)) = dispatch(&mut n, id, frame, peers.clone()) else { return };
+        drop(n);
+        if frame.header.msg_type == MessageType::RouteRequest {
+            app.messages.insert(0, format!("🔎 Buscando ruta a {}...", hex::encode(dest_id)));
+        }
+        let packet = wire::encode(&frame);
+        for peer in &targets { transport.send(&packet, *peer); }
+    };
+
     if data_to_send.len() > 800 {
         app.messages.insert(0, "📦 INICIANDO FRAGMENTACIÓN...".to_string());
         let mut rng = rand::thread_rng();
@@ -334,30 +623,273 @@ fn process_command(
         
         for chunk in chunks {
             let chunk_bytes = bincode::serialize(&chunk).unwrap();
-            let encrypted_chunk = crypto::encrypt(&chunk_bytes);
-            let frame = build_frame(id, node_id, pubkey, dest_id, MessageType::FileChunk, encrypted_chunk);
-            let packet = bincode::serialize(&frame).unwrap();
-            
-            for peer in &peers { 
-                transport.send(&packet, *peer); 
-                thread::sleep(Duration::from_millis(250));
-            }
+            let Some(encrypted_chunk) = seal(&chunk_bytes) else {
+                app.messages.insert(0, "❌ ERROR: Sin sesión con el destino".to_string());
+                return;
+            };
+            let frame = build_frame(id, node_id, pubkey, dest_id, MessageType::FileChunk, encrypted_chunk, ttl);
+            send(frame, app);
+            thread::sleep(Duration::from_millis(250));
         }
         app.messages.insert(0, "✅ ENVÍO COMPLETADO".to_string());
     } else {
-        let enc = crypto::encrypt(&data_to_send);
-        let frame = build_frame(id, node_id, pubkey, dest_id, MessageType::Chat, enc);
-        let packet = bincode::serialize(&frame).unwrap();
-        for peer in &peers { transport.send(&packet, *peer); }
+        let Some(enc) = seal(&data_to_send) else {
+            app.messages.insert(0, "❌ ERROR: Sin sesión con el destino".to_string());
+            return;
+        };
+        let frame = build_frame(id, node_id, pubkey, dest_id, MessageType::Chat, enc, ttl);
+        send(frame, app);
     }
 }
 
-fn build_frame(id: &Identity, src_id: [u8; 8], pubkey: [u8; 32], dest_id: [u8; 8], msg_type: MessageType, payload: Vec<u8>) -> Frame {
+/// /verify <ID> muestra el número de seguridad; /verify <ID> ok|no marca el contacto
+fn verify_command(args: &str, app: &mut App, node: &Arc<Mutex<Node>>, id: &Identity) {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    let Some(peer_hex) = parts.first() else { return };
+    let mut n = node.lock().unwrap();
+    let Some(peer_id) = hex::decode(peer_hex).ok().filter(|b| b.len() <= 8).and_then(|b| n.resolve_node_id(&b)) else {
+        app.messages.insert(0, format!("❌ ERROR: Nodo {} desconocido", peer_hex));
+        return;
+    };
+    let Some(peer_pubkey) = n.known_keys.pubkey(&peer_id) else {
+        app.messages.insert(0, format!("❌ ERROR: Aún no tenemos la llave de {}", hex::encode(peer_id)));
+        return;
+    };
+
+    match parts.get(1).copied() {
+        Some("ok") => {
+            n.known_keys.set_verified(&peer_id, true);
+            app.messages.insert(0, format!("✅ {} marcado como VERIFICADO", hex::encode(peer_id)));
+        },
+        Some("no") => {
+            n.known_keys.set_verified(&peer_id, false);
+            app.messages.insert(0, format!("⚠️ {} ya no está verificado", hex::encode(peer_id)));
+        },
+        _ => {
+            let code = trust::safety_number(&id.verify.to_bytes(), &peer_pubkey);
+            let status = if n.known_keys.is_verified(&peer_id) { "✅ verificado" } else { "sin verificar" };
+            app.messages.insert(0, format!("🔢 SEGURIDAD con {} ({}): {}", hex::encode(peer_id), status, code));
+            app.messages.insert(0, format!("   Compárenlo fuera de la red; si coincide: /verify {} ok", peer_hex));
+        },
+    }
+}
+
+/// /block, /unblock, /mute y /unmute <ID>. Acepta un ID completo aunque aún no lo conozcamos.
+fn block_command(command: &str, peer_hex: &str, app: &mut App, node: &Arc<Mutex<Node>>) {
+    let mut n = node.lock().unwrap();
+    let peer_id = match hex::decode(peer_hex) {
+        Ok(bytes) if bytes.len() == 8 => bytes.try_into().ok(),
+        Ok(bytes) if bytes.len() < 8 => n.resolve_node_id(&bytes),
+        _ => None,
+    };
+    let Some(peer_id) = peer_id else {
+        app.messages.insert(0, format!("❌ ERROR: Nodo {} desconocido (usa el ID completo)", peer_hex));
+        return;
+    };
+    let id_hex = hex::encode(peer_id);
+    let msg = match command {
+        "/block" if n.blocklist.set_blocked(&peer_id, true) => format!("🚫 {} BLOQUEADO", id_hex),
+        "/unblock" if n.blocklist.set_blocked(&peer_id, false) => format!("✅ {} desbloqueado", id_hex),
+        "/mute" if n.blocklist.set_muted(&peer_id, true) => format!("🔇 {} silenciado", id_hex),
+        "/unmute" if n.blocklist.set_muted(&peer_id, false) => format!("🔊 {} ya no está silenciado", id_hex),
+        _ => format!("ℹ️ {} ya estaba así", id_hex),
+    };
+    app.messages.insert(0, msg);
+}
+
+/// Canales de grupo: /join #x, /create #x, /leave #x, /ch #x <msg>, /invite #x <ID>, /kick #x <ID>, /members #x
+fn channel_command(command: &str, args: &str, app: &mut App, node: &Arc<Mutex<Node>>, id: &Identity, transport: &Transport) {
+    let (name, rest) = args.split_once(' ').map(|(n, r)| (n, r.trim())).unwrap_or((args, ""));
+    if !channels::is_valid_name(name) {
+        app.messages.insert(0, format!("❌ ERROR: '{}' no es un canal válido (ej: #medicos)", name));
+        return;
+    }
+    let mut n = node.lock().unwrap();
+    match command {
+        "/join" => {
+            let msg = match n.channels.join(name) {
+                Ok(()) => format!("📢 Entraste a {}", name),
+                Err(e) => format!("❌ ERROR: {}", e),
+            };
+            app.messages.insert(0, msg);
+        },
+        "/create" => {
+            let msg = match n.channels.create(name, id) {
+                Ok(()) => format!("📢 Canal {} creado. Invita a otros con /invite {} <ID>", name, name),
+                Err(e) => format!("❌ ERROR: {}", e),
+            };
+            app.messages.insert(0, msg);
+        },
+        "/leave" => {
+            let msg = if n.channels.leave(name) { format!("👋 Saliste de {}", name) } else { format!("ℹ️ No estabas en {}", name) };
+            app.messages.insert(0, msg);
+        },
+        "/ch" => {
+            if rest.is_empty() || rest.len() > 800 {
+                app.messages.insert(0, "❌ ERROR: Mensaje vacío o demasiado largo para un canal".to_string());
+                return;
+            }
+            let Some(msg) = n.channels.seal(name, rest.as_bytes()) else {
+                app.messages.insert(0, format!("❌ ERROR: No estás en {} (usa /join {})", name, name));
+                return;
+            };
+            let peers: Vec<SocketAddr> = n.peers.keys().cloned().collect();
+            drop(n);
+            let enc = crypto::encrypt(&bincode::serialize(&msg).unwrap());
+            let frame = build_frame(id, id.node_id(), id.verify.to_bytes(), BROADCAST_ID, MessageType::ChannelChat, enc, FLOOD_TTL);
+            let packet = wire::encode(&frame);
+            for peer in &peers { transport.send(&packet, *peer); }
+        },
+        "/members" => {
+            let Some(roster) = n.channels.roster(name) else {
+                app.messages.insert(0, format!("ℹ️ No conocemos {}", name));
+                return;
+            };
+            app.messages.insert(0, format!("👥 {} (época {}), admin {}:", name, roster.epoch, hex::encode(roster.admin)));
+            for member in roster.members {
+                let verified = if n.known_keys.is_verified(&member) { " ✅" } else { "" };
+                app.messages.insert(0, format!("   - {}{}", hex::encode(member), verified));
+            }
+        },
+        _ => {
+            let Some(peer_id) = hex::decode(rest).ok().filter(|b| b.len() <= 8).and_then(|b| n.resolve_node_id(&b)) else {
+                app.messages.insert(0, format!("❌ ERROR: Nodo {} desconocido", rest));
+                return;
+            };
+            let change = if command == "/invite" { n.channels.add_member(name, peer_id, id) } else { n.channels.remove_member(name, peer_id, id) };
+            let invite = match change {
+                Ok(invite) => invite,
+                Err(e) => { app.messages.insert(0, format!("❌ ERROR: {}", e)); return; },
+            };
+            // El nuevo estado (y la llave, si cambió) solo viaja por DM a cada miembro
+            let mut frames = Vec::new();
+            let mut sealed = 0;
+            for member in invite.members.iter().filter(|m| **m != id.node_id()) {
+                let (was_sealed, frame) = channel_state_frame(&mut n, id, *member, &invite);
+                frames.extend(frame);
+                if was_sealed {
+                    sealed += 1;
+                } else {
+                    app.messages.insert(0, format!("📮 Sin sesión con {}: le pedimos su llave y le mandaremos la época {} cuando llegue", hex::encode(member), invite.epoch));
+                }
+            }
+            drop(n);
+            for (frame, targets) in &frames {
+                let packet = wire::encode(frame);
+                for peer in targets { transport.send(&packet, *peer); }
+            }
+            let action = if command == "/invite" { "agregado a" } else { "removido de (llave nueva)" };
+            app.messages.insert(0, format!("👥 {} {} {}: época {} enviada a {} miembros", hex::encode(peer_id), action, name, invite.epoch, sealed));
+        },
+    }
+}
+
+/// /announce <archivo>: difunde un certificado de revocación (p. ej. el de un equipo perdido)
+fn announce_command(path: &str, app: &mut App, node: &Arc<Mutex<Node>>, id: &Identity, transport: &Transport) {
+    let Some(update) = fs::read_to_string(path).ok().and_then(|json| serde_json::from_str::<KeyUpdate>(&json).ok()) else {
+        app.messages.insert(0, format!("❌ ERROR: {} no es un certificado de revocación", path));
+        return;
+    };
+    if !update.is_valid() {
+        app.messages.insert(0, "❌ ERROR: La firma del certificado no es válida".to_string());
+        return;
+    }
+    let mut n = node.lock().unwrap();
+    if let Some(log) = n.apply_key_update(update.clone()) { app.messages.insert(0, log); }
+    let peers: Vec<SocketAddr> = n.peers.keys().cloned().collect();
+    drop(n);
+    let packet = wire::encode(&build_key_update(id, &update, FLOOD_TTL));
+    for peer in &peers { transport.send(&packet, *peer); }
+    app.messages.insert(0, format!("📣 Revocación anunciada a {} vecinos", peers.len()));
+}
+
+/// 🧭 A quién mandarle una trama para `dest_id` y con qué TTL: al siguiente salto
+/// si conocemos una ruta (con un margen por si cambia en el camino); si no, a todos
+fn route_to(node: &Node, dest_id: [u8; 8]) -> (Vec<SocketAddr>, u8) {
+    if dest_id != BROADCAST_ID && let Some(route) = node.routes.next_hop(&dest_id) {
+        return (vec![route.next_hop], (route.metric + 2).min(MAX_TTL as u32) as u8);
+    }
+    // 🔎 Bajo demanda no inundamos DMs: sin ruta no hay a quién mandarlos (ver `dispatch`)
+    if dest_id != BROADCAST_ID && node.routes.is_on_demand() { return (Vec::new(), MAX_TTL); }
+    (node.peers.keys().cloned().collect(), FLOOD_TTL)
+}
+
+/// Adónde va una trama ya armada. Bajo demanda y sin ruta, la trama espera en la tabla
+/// y lo que sale (si es la primera para ese destino) es un RREQ a todos los vecinos.
+fn dispatch(node: &mut Node, id: &Identity, frame: Frame, targets: Vec<SocketAddr>) -> Option<(Frame, Vec<SocketAddr>)> {
+    if !targets.is_empty() || !node.routes.is_on_demand() { return Some((frame, targets)); }
+    let dest_id = frame.header.dest_id;
+    if !node.routes.queue(dest_id, frame) { return None; }
+    let request = RouteRequest { target: dest_id, seq: node.routes.next_seq() };
+    let enc = crypto::encrypt(&bincode::serialize(&request).unwrap());
+    let rreq = build_frame(id, id.node_id(), id.verify.to_bytes(), BROADCAST_ID, MessageType::RouteRequest, enc, MAX_TTL);
+    Some((rreq, node.peers.keys().cloned().collect()))
+}
+
+/// 📮 El estado de un canal por DM a un miembro, anotado para esperar su ACK. Si todavía no
+/// tenemos su llave de DM le pedimos el Hello: el estado sale cuando llegue (false).
+fn channel_state_frame(node: &mut Node, id: &Identity, member: [u8; 8], invite: &ChannelInvite) -> (bool, Option<(Frame, Vec<SocketAddr>)>) {
+    let (targets, ttl) = route_to(node, member);
+    match node.seal_dm(member, &bincode::serialize(invite).unwrap()) {
+        Some(enc) => {
+            let frame = build_frame(id, id.node_id(), id.verify.to_bytes(), member, MessageType::ChannelKey, enc, ttl);
+            node.channels.awaiting_ack(frame.header.msg_id, member, invite);
+            (true, dispatch(node, id, frame, targets))
+        },
+        None => (false, dispatch(node, id, build_hello(id, member, FLAG_WANT_HELLO, ttl), targets)),
+    }
+}
+
+/// Tramas con los certificados de revocación/rotación recientes, solo para los vecinos (TTL 1)
+fn key_update_packets(id: &Identity, node: &Node) -> Vec<Vec<u8>> {
+    node.revoked.recent().into_iter().map(|u| wire::encode(&build_key_update(id, u, 1))).collect()
+}
+
+fn build_key_update(id: &Identity, update: &KeyUpdate, ttl: u8) -> Frame {
+    let enc = crypto::encrypt(&bincode::serialize(update).unwrap());
+    build_frame(id, id.node_id(), id.verify.to_bytes(), BROADCAST_ID, MessageType::KeyUpdate, enc, ttl)
+}
+
+/// RERR con las rutas que se nos rompieron, solo para los vecinos (TTL 1)
+fn build_route_error(id: &Identity, unreachable: Vec<routing::AdvertEntry>) -> Frame {
+    let enc = crypto::encrypt(&bincode::serialize(&RouteError { unreachable }).unwrap());
+    build_frame(id, id.node_id(), id.verify.to_bytes(), BROADCAST_ID, MessageType::RouteError, enc, 1)
+}
+
+/// Hello firmado que anuncia nuestra llave X25519 para los DMs. A todos (inundado) o,
+/// para un nodo lejano, dirigido por su ruta (con FLAG_WANT_HELLO pedimos el suyo)
+fn build_hello(id: &Identity, dest_id: [u8; 8], flags: u8, ttl: u8) -> Frame {
+    let hello = Hello { dh_pubkey: id.x25519_public() };
+    let enc = crypto::encrypt(&bincode::serialize(&hello).unwrap());
+    let mut frame = build_frame(id, id.node_id(), id.verify.to_bytes(), dest_id, MessageType::Hello, enc, ttl);
+    if flags != 0 {
+        frame.header.flags = flags;
+        sign_frame(id, &mut frame);
+    }
+    frame
+}
+
+/// Sondeo con los ecos para medir los enlaces, solo para los vecinos directos (TTL 1)
+fn build_link_probe(id: &Identity, echoes: Vec<neighbor::Echo>) -> Frame {
+    let enc = crypto::encrypt(&bincode::serialize(&LinkProbe { echoes }).unwrap());
+    build_frame(id, id.node_id(), id.verify.to_bytes(), BROADCAST_ID, MessageType::LinkProbe, enc, 1)
+}
+
+/// Lista de vecinos firmada, solo para los vecinos directos (TTL 1)
+fn build_peer_list(id: &Identity, list: &PeerList) -> Frame {
+    let enc = crypto::encrypt(&bincode::serialize(list).unwrap());
+    build_frame(id, id.node_id(), id.verify.to_bytes(), BROADCAST_ID, MessageType::PeerList, enc, 1)
+}
+
+fn build_frame(id: &Identity, src_id: [u8; 8], pubkey: [u8; 32], dest_id: [u8; 8], msg_type: MessageType, payload: Vec<u8>, ttl: u8) -> Frame {
     let mut rng = rand::thread_rng();
     let msg_id = rng.next_u64();
-    let header = Header { magic: MAGIC_BYTES, version: CURRENT_VERSION, msg_type, ttl: 3, flags: 0, msg_id, src_id, dest_id, sender_pubkey: pubkey, payload_len: payload.len() as u16 };
-    let mut h2 = header.clone(); h2.ttl = 0; h2.flags = 0;
-    let mut d = bincode::serialize(&h2).unwrap(); d.extend_from_slice(&payload);
-    let sig = id.signing.sign(&d).to_bytes().to_vec();
-    Frame { header, payload, signature: sig }
+    let header = Header { magic: MAGIC_BYTES, version: CURRENT_VERSION, network_id: crypto::network_id(), msg_type, initial_ttl: ttl, flags: 0, msg_id, timestamp_ms: protocol::unix_millis(), src_id, dest_id, sender_pubkey: pubkey, pow_nonce: pow::stamp(), payload_len: payload.len() as u16 };
+    let mut frame = Frame::new(header, payload);
+    sign_frame(id, &mut frame);
+    frame
+}
+
+fn sign_frame(id: &Identity, frame: &mut Frame) {
+    frame.signature = id.signing.sign(&frame.signable_bytes()).to_bytes().to_vec();
 }
\ No newline at end of file
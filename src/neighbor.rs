@@ -0,0 +1,110 @@
+use crate::routing::INFINITY;
+use serde::{Serialize, Deserialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// 📶 Calidad de cada enlace directo. Los sondeos (LinkProbe) salen cada PROBE_INTERVAL:
+// contando cuántos nos llegan (y cuántos de los nuestros le llegan al otro, que nos lo
+// cuenta en su eco) sale la tasa de entrega en cada sentido, y de ahí el ETX = 1 / (df · dr),
+// las transmisiones esperadas para que una trama pase el enlace.
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+const WINDOW: Duration = Duration::from_secs(60);
+const RTT_WEIGHT: f64 = 0.25; // Peso de cada muestra nueva en el promedio del RTT
+const MAX_RTT: Duration = Duration::from_secs(10); // Muestras más largas son basura (p. ej. un sondeo viejo)
+pub const MAX_ECHOES: usize = 32;
+
+/// 🔁 Lo que va en nuestro sondeo sobre el último sondeo de un vecino
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Echo {
+    pub node_id: [u8; 8],
+    pub timestamp_ms: u64, // Su timestamp_ms, tal cual (así el RTT sale con su propio reloj)
+    pub held_ms: u32,      // Cuánto pasó desde que nos llegó hasta este sondeo
+    pub delivery: u8,      // % de sus sondeos que nos llegaron
+}
+
+pub struct Neighbor {
+    pub last_seen: Instant,
+    pub node_id: Option<[u8; 8]>, // Se conoce con su primer sondeo
+    // Su sondeo (firmado) trajo el eco del nuestro: nos oye, y no es un sondeo ajeno repetido
+    // por otro desde esta dirección (ese no tendría nuestro eco)
+    pub confirmed: bool,
+    pub rtt: Option<Duration>,
+    first_probe: Option<Instant>,
+    probes: VecDeque<Instant>, // Sondeos recibidos dentro de WINDOW
+    last_probe: Option<(u64, Instant)>, // (su timestamp_ms, cuándo llegó) para el eco
+    forward: Option<f64>, // Cuánto de lo nuestro le llega (según su eco)
+}
+
+impl Neighbor {
+    pub fn new(now: Instant) -> Self {
+        Self { last_seen: now, node_id: None, confirmed: false, rtt: None, first_probe: None, probes: VecDeque::new(), last_probe: None, forward: None }
+    }
+
+    /// Un sondeo de este vecino
+    pub fn on_probe(&mut self, node_id: [u8; 8], timestamp_ms: u64, now: Instant) {
+        if self.node_id != Some(node_id) {
+            // Otro nodo en la misma dirección: lo medido era de otro enlace
+            *self = Self::new(now);
+            self.node_id = Some(node_id);
+        }
+        self.last_seen = now;
+        self.first_probe.get_or_insert(now);
+        self.probes.push_back(now);
+        while self.probes.front().is_some_and(|t| now.duration_since(*t) > WINDOW) { self.probes.pop_front(); }
+        self.last_probe = Some((timestamp_ms, now));
+    }
+
+    /// El eco de nuestro último sondeo que nos devolvió (now_ms y el timestamp son de nuestro reloj)
+    pub fn on_echo(&mut self, echo: &Echo, now_ms: u64) {
+        self.confirmed = true;
+        self.forward = Some(echo.delivery.min(100) as f64 / 100.0);
+        let elapsed = now_ms.saturating_sub(echo.timestamp_ms).saturating_sub(echo.held_ms as u64);
+        if echo.timestamp_ms <= now_ms { self.rtt_sample(Duration::from_millis(elapsed)); }
+    }
+
+    pub fn rtt_sample(&mut self, sample: Duration) {
+        if sample > MAX_RTT { return; }
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => rtt.mul_f64(1.0 - RTT_WEIGHT) + sample.mul_f64(RTT_WEIGHT),
+            None => sample,
+        });
+    }
+
+    /// Fracción de sus sondeos que nos llegaron en la ventana
+    pub fn reverse_delivery(&self, now: Instant) -> Option<f64> {
+        let first = self.first_probe?;
+        let span = now.duration_since(first).min(WINDOW);
+        let expected = (span.as_secs_f64() / PROBE_INTERVAL.as_secs_f64()).floor() + 1.0;
+        let received = self.probes.iter().filter(|t| now.duration_since(**t) <= WINDOW).count() as f64;
+        Some((received / expected).min(1.0))
+    }
+
+    /// Fracción de nuestros sondeos que le llegaron (lo que dijo en su último eco)
+    pub fn forward_delivery(&self) -> Option<f64> {
+        self.forward
+    }
+
+    /// Transmisiones esperadas por trama. Sin eco todavía suponemos el enlace simétrico.
+    pub fn etx(&self, now: Instant) -> Option<f64> {
+        let reverse = self.reverse_delivery(now)?;
+        let forward = self.forward.unwrap_or(reverse);
+        if forward * reverse <= 0.0 { return Some(INFINITY as f64); }
+        Some((1.0 / (forward * reverse)).min(INFINITY as f64))
+    }
+
+    /// Costo del enlace para las rutas: el ETX redondeado (1 = enlace perfecto)
+    pub fn link_cost(&self, now: Instant) -> u32 {
+        self.etx(now).map_or(1, |etx| (etx.round() as u32).clamp(1, INFINITY))
+    }
+
+    /// Eco para nuestro próximo sondeo
+    pub fn echo(&self, now: Instant) -> Option<Echo> {
+        let (timestamp_ms, received) = self.last_probe?;
+        Some(Echo {
+            node_id: self.node_id?,
+            timestamp_ms,
+            held_ms: now.duration_since(received).as_millis().min(u32::MAX as u128) as u32,
+            delivery: (self.reverse_delivery(now)? * 100.0).round() as u8,
+        })
+    }
+}
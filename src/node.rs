@@ -1,111 +1,446 @@
-use crate::protocol::{Frame, MessageType, BROADCAST_ID};
+use crate::protocol::{self, Frame, Hello, KeyUpdate, LinkProbe, MessageType, PeerEntry, PeerList, BROADCAST_ID, FLAG_WANT_HELLO};
 use crate::replay_cache::{ReplayCache, ReplayKey};
-use crate::rate_limiter::RateLimiter;
+use crate::rate_limiter::{self, RateLimiter};
 use crate::crypto;
 use crate::chunker::{Assembler, Chunk};
+use crate::identity::node_id_from_pubkey;
+use crate::trust::{KeyCheck, KnownKeys};
+use crate::session::{PeerKeys, SessionStore};
+use crate::revocation::RevokedKeys;
+use crate::pow;
+use crate::blocklist::BlockList;
+use crate::channels::{ChannelInvite, ChannelMessage, ChannelStore};
+use crate::profile::Profile;
+use crate::address_book::AddressBook;
+use crate::transport;
+use crate::neighbor::{Echo, Neighbor, MAX_ECHOES};
+use crate::peer_exchange::{Candidates, MAX_AGE_SECS, MAX_PEER_LIST};
+use crate::routing::{RouteAdvert, RouteError, RouteReply, RouteRequest, RouteTable};
 use ed25519_dalek::{Verifier, VerifyingKey, Signature};
+use x25519_dalek::{PublicKey, StaticSecret};
 use std::convert::TryInto;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::time::{Instant, Duration};
 use std::fs::{self, File};
 use std::io::Write;
 
+const MAX_CLOCK_WARNED: usize = 256; // Nodos distintos que avisamos por reloj desfasado
+
 #[derive(Debug)]
 pub enum State { Idle, Processing }
 
 // ⚠️ ESTO ES LO QUE FALTABA: log_output
 pub struct ProcessResult {
-    pub frame_to_relay: Option<Frame>,         
-    pub ack_to_send: Option<(SocketAddr, u64)>,
+    pub frame_to_relay: Option<Frame>,
+    pub relay_to: Option<SocketAddr>, // 🧭 Siguiente salto; None = a todos menos a quien la trajo
+    pub ack_to_send: Option<([u8; 8], u64)>, // (emisor original, msg_id)
+    pub route_reply_to: Option<[u8; 8]>, // 🔎 Nos buscaron con un RREQ: hay que responderle a este origen
+    pub peer_list_to: Option<SocketAddr>, // 🤝 Vecino nuevo: le pasamos nuestro Hello, la lista de vecinos y las revocaciones
+    pub hello_to: Option<[u8; 8]>, // 🔑 Nos pidieron la llave de DM: le mandamos nuestro Hello por su ruta
+    pub invites_to: Option<[u8; 8]>, // 📮 Llegó la llave de un miembro al que le debíamos estados de canal
     pub log_output: Option<String>, // 👈 El canal hacia la pantalla
 }
 
+/// Estado persistente del nodo (todo vive en la carpeta del perfil)
+pub struct NodeStores {
+    pub known_keys: KnownKeys,
+    pub sessions: SessionStore,
+    pub revoked: RevokedKeys,
+    pub replay_cache: ReplayCache,
+    pub blocklist: BlockList,
+    pub channels: ChannelStore,
+    pub address_book: AddressBook,
+}
+
+impl NodeStores {
+    pub fn load(profile: &Profile) -> Result<Self, String> {
+        Ok(Self {
+            known_keys: KnownKeys::load(profile.known_keys_path())?,
+            sessions: SessionStore::load(profile.sessions_dir()),
+            revoked: RevokedKeys::load(profile.revoked_path())?,
+            replay_cache: ReplayCache::load(profile.replay_path()),
+            blocklist: BlockList::load(profile.blocklist_path()),
+            channels: ChannelStore::load(profile.channels_path()),
+            address_book: AddressBook::load(profile.peers_path()),
+        })
+    }
+}
+
+/// Opciones de arranque que cambian cómo el nodo trata las tramas
+pub struct NodeOptions {
+    pub pow_bits: u8,        // ⛏️ 0 = no se exige prueba de trabajo
+    pub relay_blocked: bool, // 🚫 ¿Seguimos reenviando lo de los bloqueados para los demás?
+    pub on_demand: bool,     // 🔎 Rutas con RREQ/RREP en vez de anuncios periódicos
+}
+
 pub struct Node {
     pub state: State,
     pub my_id: [u8; 8],
     replay_cache: ReplayCache,
     rate_limiter: RateLimiter,
-    pub peers: HashMap<SocketAddr, Instant>,
+    pub peers: HashMap<SocketAddr, Neighbor>, // 📶 Vecinos directos y la calidad de su enlace
     assembler: Assembler,
+    dh_secret: StaticSecret,
+    // 🔑 Llaves X25519 estáticas anunciadas en los Hello (node_id -> llave pública)
+    peer_dh_keys: HashMap<[u8; 8], [u8; 32]>,
+    // 🔁 Sesiones de trinquete para los DMs
+    sessions: SessionStore,
+    network_id: [u8; 8],
+    // Direcciones que ya avisamos por pertenecer a otra malla (para no inundar el log)
+    foreign_meshes: HashSet<SocketAddr>,
+    pub known_keys: KnownKeys,
+    pub revoked: RevokedKeys,
+    // Nodos revocados que ya avisamos (para no inundar el log)
+    revoked_senders: HashSet<[u8; 8]>,
+    // Nodos con el reloj fuera de la ventana que ya avisamos
+    clock_warned: HashSet<[u8; 8]>,
+    // ⛏️ Bits de prueba de trabajo exigidos (0 = no se exige) y llaves que ya la mostraron
+    pow_bits: u8,
+    pow_ok: HashSet<[u8; 32]>,
+    pow_rejected: HashSet<[u8; 8]>,
+    pub blocklist: BlockList,
+    relay_blocked: bool,
+    pub channels: ChannelStore,
+    pub routes: RouteTable,
+    // ⏱️ DMs enviados esperando su ACK (msg_id -> cuándo), para medir el RTT
+    pending_acks: HashMap<u64, Instant>,
+    // 📡 Nodos que ya avisamos como descubiertos en la LAN
+    discovered: HashSet<[u8; 8]>,
+    // 🤝 Direcciones que otros nos presentaron, a probar con un sondeo
+    pub candidates: Candidates,
+    pub address_book: AddressBook,
 }
 
 impl Node {
-    pub fn new(my_id: [u8; 8]) -> Self {
+    pub fn new(my_id: [u8; 8], dh_secret: StaticSecret, stores: NodeStores, options: NodeOptions) -> Self {
         let _ = fs::create_dir_all("downloads");
         Self {
             state: State::Idle,
             my_id,
-            replay_cache: ReplayCache::new(),
+            replay_cache: stores.replay_cache,
             rate_limiter: RateLimiter::new(),
             peers: HashMap::new(),
             assembler: Assembler::new(),
+            dh_secret,
+            peer_dh_keys: HashMap::new(),
+            sessions: stores.sessions,
+            network_id: crypto::network_id(),
+            foreign_meshes: HashSet::new(),
+            known_keys: stores.known_keys,
+            revoked: stores.revoked,
+            revoked_senders: HashSet::new(),
+            clock_warned: HashSet::new(),
+            pow_bits: options.pow_bits,
+            pow_ok: HashSet::new(),
+            pow_rejected: HashSet::new(),
+            blocklist: stores.blocklist,
+            relay_blocked: options.relay_blocked,
+            channels: stores.channels,
+            routes: RouteTable::new(my_id, options.on_demand),
+            pending_acks: HashMap::new(),
+            discovered: HashSet::new(),
+            candidates: Candidates::new(),
+            address_book: stores.address_book,
         }
     }
 
     pub fn add_peer(&mut self, addr: SocketAddr) {
-        self.peers.insert(addr, Instant::now());
+        self.peers.entry(addr).or_insert_with(|| Neighbor::new(Instant::now())).last_seen = Instant::now();
+        self.candidates.remove(&addr);
+    }
+
+    /// 🤝 Los vecinos directos (confirmados con su sondeo) escuchados hace poco, los más frescos primero
+    pub fn peer_list(&self) -> PeerList {
+        let now = Instant::now();
+        let mut entries: Vec<PeerEntry> = self.peers.iter()
+            .filter(|(_, n)| n.confirmed)
+            .filter_map(|(addr, n)| Some(PeerEntry {
+                addr: *addr,
+                node_id: n.node_id?,
+                age_secs: now.duration_since(n.last_seen).as_secs().min(u32::MAX as u64) as u32,
+            }))
+            .filter(|e| e.age_secs <= MAX_AGE_SECS)
+            .collect();
+        entries.sort_by_key(|e| e.age_secs);
+        entries.truncate(MAX_PEER_LIST);
+        PeerList { entries }
+    }
+
+    /// Anota un DM enviado: su ACK, si llega directo, es una muestra de RTT
+    pub fn expect_ack(&mut self, msg_id: u64) {
+        self.pending_acks.insert(msg_id, Instant::now());
+    }
+
+    /// Ecos para nuestro próximo sondeo (los vecinos más recientes primero)
+    pub fn probe_echoes(&self) -> Vec<Echo> {
+        let now = Instant::now();
+        let mut echoes: Vec<Echo> = self.peers.values().filter_map(|n| n.echo(now)).collect();
+        echoes.sort_by_key(|e| e.held_ms);
+        echoes.truncate(MAX_ECHOES);
+        echoes
+    }
+
+    /// Costo (ETX redondeado) del enlace con un vecino
+    fn link_cost(&self, addr: &SocketAddr) -> u32 {
+        self.peers.get(addr).map_or(1, |n| n.link_cost(Instant::now()))
+    }
+
+    /// Cifra un DM por la sesión de trinquete (hace falta su Hello o una sesión guardada)
+    pub fn seal_dm(&mut self, peer_id: [u8; 8], plaintext: &[u8]) -> Option<Vec<u8>> {
+        let keys = peer_keys(self.my_id, &self.dh_secret, &self.peer_dh_keys, peer_id);
+        self.sessions.encrypt(peer_id, &self.dh_secret, keys, &crypto::pad(plaintext))
+    }
+
+    fn open_dm(&mut self, peer_id: [u8; 8], data: &[u8]) -> Option<Vec<u8>> {
+        let keys = peer_keys(self.my_id, &self.dh_secret, &self.peer_dh_keys, peer_id);
+        self.sessions.decrypt(peer_id, &self.dh_secret, keys, data).and_then(|p| crypto::unpad(&p))
+    }
+
+    /// Completa un ID parcial (como los que muestra el chat) con los nodos conocidos
+    /// (también los lejanos que solo conocemos por la tabla de rutas)
+    pub fn resolve_node_id(&self, prefix: &[u8]) -> Option<[u8; 8]> {
+        let known: HashSet<[u8; 8]> = self.peer_dh_keys.keys().chain(self.sessions.peer_ids()).copied()
+            .chain(self.known_keys.ids())
+            .chain(self.routes.routes().map(|(dest, _)| *dest)).collect();
+        let mut matches = known.into_iter().filter(|id| id.starts_with(prefix));
+        let found = matches.next()?;
+        if matches.next().is_some() { return None; } // Ambiguo
+        Some(found)
+    }
+
+    /// ¿Podemos cifrarle un DM? (tenemos su llave X25519 o una sesión guardada)
+    pub fn has_dm_key(&self, peer_id: &[u8; 8]) -> bool {
+        self.peer_dh_keys.contains_key(peer_id) || self.sessions.peer_ids().any(|id| id == peer_id)
+    }
+
+    /// Trama de otra malla (avisamos una sola vez por dirección)
+    pub fn on_foreign_mesh(&mut self, src: SocketAddr, network_id: [u8; 8]) -> Option<String> {
+        if !self.foreign_meshes.insert(src) { return None; }
+        Some(format!("🚧 {} pertenece a otra malla (red {}), ignorado", src, hex::encode(network_id)))
+    }
+
+    /// Guarda en disco las marcas anti-repetición (se llama periódicamente y al salir)
+    pub fn save_replay_state(&mut self) {
+        self.replay_cache.save(protocol::unix_millis());
+    }
+
+    /// 📒 Guarda los vecinos actuales en la libreta (periódicamente y al salir)
+    pub fn save_address_book(&mut self) {
+        self.address_book.record(self.peers.iter());
+    }
+
+    /// Direcciones baneadas por mal comportamiento (para /status)
+    pub fn bans(&self) -> Vec<(SocketAddr, Duration)> {
+        self.rate_limiter.bans()
     }
 
     pub fn prune_dead_nodes(&mut self, timeout: Duration) -> Vec<SocketAddr> {
-        self.assembler.cleanup_stale(); 
+        self.assembler.cleanup_stale();
+        self.rate_limiter.cleanup();
         let now = Instant::now();
         let mut dead_nodes = Vec::new();
-        self.peers.retain(|addr, last_seen| {
-            if now.duration_since(*last_seen) > timeout {
+        self.pending_acks.retain(|_, sent| now.duration_since(*sent) < timeout * 4);
+        self.peers.retain(|addr, neighbor| {
+            if now.duration_since(neighbor.last_seen) > timeout {
                 dead_nodes.push(*addr);
                 false
             } else { true }
         });
+        for addr in &dead_nodes { self.routes.link_down(*addr); }
+        self.routes.expire();
         dead_nodes
     }
 
     pub fn on_frame(&mut self, mut frame: Frame, src: SocketAddr) -> ProcessResult {
         self.state = State::Processing;
         // Inicializamos log_output como None
-        let mut result = ProcessResult { frame_to_relay: None, ack_to_send: None, log_output: None };
+        let mut result = ProcessResult { frame_to_relay: None, relay_to: None, ack_to_send: None, route_reply_to: None, peer_list_to: None, hello_to: None, invites_to: None, log_output: None };
+
+        // 🚫 Baneados: ni siquiera miramos la trama
+        if self.rate_limiter.is_banned(&src) {
+            self.state = State::Idle; return result;
+        }
 
         if !frame.is_valid_structure() {
             self.state = State::Idle; return result;
         }
 
-        let key = ReplayKey { sender: frame.header.src_id, msg_id: frame.header.msg_id.to_le_bytes() };
-        if self.replay_cache.seen(key) { self.state = State::Idle; return result; }
+        // 🚧 Otra malla (otra clave de red): la descartamos antes de verificar o descifrar
+        if frame.header.network_id != self.network_id {
+            result.log_output = self.on_foreign_mesh(src, frame.header.network_id);
+            self.state = State::Idle; return result;
+        }
+
+        // 🪣 Presupuesto del vecino, antes de gastar CPU verificando firmas. Lo que se pasa se
+        // descarta, pero solo suma puntaje si es tráfico propio del vecino (sin saltos): un relay
+        // honesto puede pasarse reenviando los Hello de toda la malla y no debe terminar baneado.
+        // Quien mienta en los saltos para librarse del puntaje sigue frenado por la cubeta.
+        let msg_type = frame.header.msg_type;
+        if !self.rate_limiter.allow_addr(src, msg_type) {
+            if frame.hops.hop_count == 0 { self.flood_penalty(src, &mut result); }
+            self.state = State::Idle; return result;
+        }
+
+        // ⏰ Fuera de la ventana de tiempo: o es una repetición vieja o el reloj del emisor está mal.
+        // Se descarta sin más; solo verificamos la firma para no avisar (ni recordar) a nombre de
+        // un src_id que cualquiera pudo escribir en la cabecera.
+        let now_ms = protocol::unix_millis();
+        if !ReplayCache::in_window(frame.header.timestamp_ms, now_ms) {
+            if self.clock_warned.len() < MAX_CLOCK_WARNED
+                && self.verify_signature(&frame)
+                && node_id_from_pubkey(&frame.header.sender_pubkey) == frame.header.src_id
+                && self.clock_warned.insert(frame.header.src_id) {
+                result.log_output = Some(format!("⏰ Trama de [{:02x?}] fuera de la ventana de tiempo (¿repetida o reloj desfasado?)", &frame.header.src_id[0..4]));
+            }
+            self.state = State::Idle; return result;
+        }
 
         if !self.verify_signature(&frame) {
             // Enviamos el error a la pantalla en vez de println!
-            result.log_output = Some(format!("⛔ Firma inválida de {}", src));
+            result.log_output = Some(if self.rate_limiter.penalize_invalid_signature(src) {
+                format!("🚫 {} baneado por inundar con firmas inválidas", src)
+            } else {
+                format!("⛔ Firma inválida de {}", src)
+            });
+            self.state = State::Idle; return result;
+        }
+
+        // 🆔 El src_id debe ser el que corresponde a la llave que firmó
+        let sender_pubkey = frame.header.sender_pubkey;
+        if node_id_from_pubkey(&sender_pubkey) != frame.header.src_id {
+            result.log_output = Some(format!("⛔ src_id falso desde {} (no coincide con su llave)", src));
+            self.state = State::Idle; return result;
+        }
+
+        // 🪞 Nuestras propias tramas (un beacon multicast que vuelve, el eco de una inundación)
+        if frame.header.src_id == self.my_id {
+            self.state = State::Idle; return result;
+        }
+
+        // ⛏️ Identidades sin trabajo suficiente: ni se muestran ni se reenvían
+        if !self.has_pow(&frame) {
+            if self.pow_rejected.insert(frame.header.src_id) {
+                result.log_output = Some(format!("⛏️ [{:02x?}] no trae prueba de trabajo de {} bits: descartado", &frame.header.src_id[0..4], self.pow_bits));
+            }
+            self.state = State::Idle; return result;
+        }
+
+        // 🔁 Repeticiones: solo después de la firma, para que nadie pueda envenenar
+        // la caché (ni subir el piso de otro) con tramas inventadas
+        let key = ReplayKey { sender: frame.header.src_id, msg_id: frame.header.msg_id.to_le_bytes() };
+        if self.replay_cache.seen(key, frame.header.timestamp_ms, now_ms) { self.state = State::Idle; return result; }
+
+        // 🪣 Presupuesto del emisor original (ya sabemos que la firma es suya).
+        // No castigamos al vecino: puede ser un relay honesto reenviando al que inunda.
+        if !self.rate_limiter.allow_src(frame.header.src_id, msg_type) {
+            self.state = State::Idle; return result;
+        }
+
+        // 🪦 Llave revocada: no aceptamos ni reenviamos nada firmado con ella
+        if self.revoked.is_revoked(&sender_pubkey) {
+            if self.revoked_senders.insert(frame.header.src_id) {
+                result.log_output = Some(format!("🪦 Tramas de [{}] descartadas: su llave está REVOCADA", hex::encode(frame.header.src_id)));
+            }
             self.state = State::Idle; return result;
         }
 
-        self.peers.insert(src, Instant::now());
+        // 📌 TOFU: un ID conocido no puede aparecer con otra llave
+        let key_check = self.known_keys.check_and_pin(&frame.header.src_id, &sender_pubkey);
+        if let KeyCheck::Mismatch = key_check {
+            result.log_output = Some(format!(
+                "🚨 ALERTA: [{}] llegó con una LLAVE DISTINTA a la fijada ({}) desde {}. ¡Posible suplantación! Trama descartada",
+                hex::encode(frame.header.src_id), hex::encode(sender_pubkey), src));
+            self.state = State::Idle; return result;
+        }
+
+        // 🚫 Bloqueado: no lo procesamos; solo lo dejamos pasar si así se configuró
+        if self.blocklist.is_blocked(&frame.header.src_id) {
+            if self.relay_blocked && frame.header.dest_id != self.my_id && frame.forward(self.my_id) {
+                result.frame_to_relay = Some(frame);
+            }
+            self.state = State::Idle; return result;
+        }
+
+        self.add_peer(src);
 
         let is_broadcast = frame.header.dest_id == BROADCAST_ID;
         let is_for_me = frame.header.dest_id == self.my_id;
 
+        // 🔎 RREQ y RREP dejan a su paso la ruta hacia quien los firmó
+        self.learn_route(&frame, src);
+        self.routes.refresh(&frame.header.src_id, src);
+
         if !is_broadcast && !is_for_me {
-            if frame.header.msg_type != MessageType::PeerList && frame.header.msg_type != MessageType::Hello {
-                if frame.decrement_ttl() { result.frame_to_relay = Some(frame); }
-                self.state = State::Idle;
-                return result;
-            }
+            // 🧭 Con ruta conocida va solo al siguiente salto; si no, inundamos como antes
+            result.relay_to = self.routes.next_hop(&frame.header.dest_id).map(|r| r.next_hop).filter(|hop| *hop != src);
+            if let Some(next_hop) = result.relay_to { self.routes.refresh(&frame.header.dest_id, next_hop); }
+            if frame.header.msg_type != MessageType::RouteError && frame.forward(self.my_id) { result.frame_to_relay = Some(frame); }
+            self.state = State::Idle;
+            return result;
         }
 
-        match crypto::decrypt(&frame.payload) {
+        // Los DMs van por la sesión de trinquete; el resto (y el RREP y el Hello dirigido) con la clave de red
+        let decrypted = if is_for_me && !matches!(frame.header.msg_type, MessageType::RouteReply | MessageType::Hello) {
+            match self.open_dm(frame.header.src_id, &frame.payload) {
+                Some(plaintext) => Some(plaintext),
+                None => {
+                    result.log_output = Some(format!("🔒 No se pudo descifrar el DM de [{:02x?}] (sin sesión o desincronizada)", &frame.header.src_id[0..4]));
+                    self.state = State::Idle; return result;
+                }
+            }
+        } else {
+            crypto::decrypt(&frame.payload)
+        };
+
+        match decrypted {
             Some(decrypted_payload) => {
                 match frame.header.msg_type {
                     MessageType::Hello => {
-                         if self.peers.insert(src, Instant::now()).is_none() { 
-                             result.log_output = Some(format!("👋 NUEVO VECINO: {}", src)); 
-                         }
+                        if let Ok(hello) = bincode::deserialize::<Hello>(&decrypted_payload) {
+                            if self.learn_dh_key(frame.header.src_id, hello.dh_pubkey) {
+                                result.log_output = Some(format!("🔑 Llave de DM de [{:02x?}] recibida", &frame.header.src_id[0..4]));
+                                if self.channels.owes(&frame.header.src_id) { result.invites_to = Some(frame.header.src_id); }
+                            }
+                            // Un nodo lejano (más allá de la inundación de los Hello) nos pidió el nuestro
+                            if is_for_me && frame.header.flags & FLAG_WANT_HELLO != 0 {
+                                result.hello_to = Some(frame.header.src_id);
+                            }
+                        }
                     },
-                    MessageType::PeerList => {
-                        if let Ok(new_peers) = bincode::deserialize::<Vec<SocketAddr>>(&decrypted_payload) {
-                            for peer in new_peers {
-                                if !self.peers.contains_key(&peer) && peer != src {
-                                    self.peers.insert(peer, Instant::now());
-                                    result.log_output = Some(format!("💡 Ruta aprendida: {}", peer));
-                                }
+                    // 📶 Solo los sondeos directos (firmados con TTL 1) miden el enlace con quien nos lo
+                    // trajo. Uno repetido por otro cambia de dueño la dirección, pero no queda
+                    // confirmado hasta que traiga el eco de nuestro sondeo.
+                    MessageType::LinkProbe if frame.header.initial_ttl == 1 => {
+                        if let Ok(probe) = bincode::deserialize::<LinkProbe>(&decrypted_payload) {
+                            let neighbor = self.peers.entry(src).or_insert_with(|| Neighbor::new(Instant::now()));
+                            neighbor.on_probe(frame.header.src_id, frame.header.timestamp_ms, Instant::now());
+                            let was_confirmed = neighbor.confirmed;
+                            if let Some(echo) = probe.echoes.iter().find(|e| e.node_id == self.my_id) {
+                                neighbor.on_echo(echo, protocol::unix_millis());
+                            }
+                            if neighbor.confirmed && !was_confirmed {
+                                result.log_output = Some(format!("👋 NUEVO VECINO: {} ({})", src, hex::encode(frame.header.src_id)));
+                                result.peer_list_to = Some(src);
+                            }
+                        }
+                    },
+                    // Solo de un vecino directo que ya se presentó con su sondeo, y de tamaño acotado
+                    MessageType::PeerList if frame.header.initial_ttl == 1 && self.is_direct(&frame, &src) => {
+                        if let Ok(list) = bincode::deserialize::<PeerList>(&decrypted_payload)
+                            && list.entries.len() <= MAX_PEER_LIST {
+                            // 🔗 Las de enlace local están en el mismo enlace que quien nos las pasó
+                            let fresh: Vec<PeerEntry> = list.entries.into_iter()
+                                .map(|e| PeerEntry { addr: transport::fix_scope(transport::canonical(e.addr), src), ..e })
+                                .filter(|e| {
+                                    e.age_secs <= MAX_AGE_SECS && e.node_id != self.my_id
+                                        && transport::is_routable(&e.addr)
+                                        && !self.peers.contains_key(&e.addr)
+                                        && !self.blocklist.is_blocked(&e.node_id)
+                                }).collect();
+                            let added = self.candidates.offer(frame.header.src_id, fresh.iter());
+                            if added > 0 {
+                                result.log_output = Some(format!("💡 {} le presentó {} posibles vecinos (probando)", self.sender_tag(&frame.header.src_id), added));
                             }
                         }
                     },
@@ -113,50 +448,123 @@ impl Node {
                         let texto = String::from_utf8_lossy(&decrypted_payload);
                         if is_for_me && !is_broadcast {
                             // Privado
-                            result.log_output = Some(format!("🕵️‍♂️ PRIVADO DE [{:02x?}]: {}", &frame.header.src_id[0..4], texto));
-                            result.ack_to_send = Some((src, frame.header.msg_id));
+                            result.log_output = Some(format!("🕵️‍♂️ PRIVADO DE {}: {}", self.sender_tag(&frame.header.src_id), texto));
+                            result.ack_to_send = Some((frame.header.src_id, frame.header.msg_id));
                         } else {
                             // Chat normal
-                            result.log_output = Some(format!("💬 [{:02x?}] dice: {}", &frame.header.src_id[0..4], texto));
+                            result.log_output = Some(format!("💬 {} dice: {}", self.sender_tag(&frame.header.src_id), texto));
                         }
-                        if !self.peers.contains_key(&src) { self.peers.insert(src, Instant::now()); }
+                        // 🔇 Silenciado: se procesa (y se confirma) pero no se muestra
+                        if self.blocklist.is_muted(&frame.header.src_id) { result.log_output = None; }
                     },
-                    MessageType::FileChunk => {
-                        if is_for_me || is_broadcast {
-                            if let Ok(chunk) = bincode::deserialize::<Chunk>(&decrypted_payload) {
-                                // Notificar cada 50 paquetes para ver que está vivo
-                                if chunk.index % 50 == 0 {
-                                     // result.log_output = Some(format!("⏳ Bajando... {}/{}", chunk.index, chunk.total));
-                                }
-                                
-                                if let Some(full_data) = self.assembler.add_chunk(chunk) {
-                                    if full_data.starts_with(b"FILE:") {
-                                        if let Some(separator_index) = full_data.iter().position(|&r| r == b'|') {
-                                            let name_part = &full_data[5..separator_index];
-                                            let file_content = &full_data[separator_index + 1..];
-                                            let filename = String::from_utf8_lossy(name_part);
-                                            let path = format!("downloads/{}", filename);
-                                            match File::create(&path) {
-                                                Ok(mut file) => {
-                                                    let _ = file.write_all(file_content);
-                                                    result.log_output = Some(format!("💾 ARCHIVO GUARDADO: {}", path));
-                                                },
-                                                Err(e) => result.log_output = Some(format!("❌ Error disco: {}", e)),
-                                            }
+                    MessageType::FileChunk if is_for_me || is_broadcast => {
+                        if let Ok(chunk) = bincode::deserialize::<Chunk>(&decrypted_payload) {
+                            // Notificar cada 50 paquetes para ver que está vivo
+                            if chunk.index % 50 == 0 {
+                                 // result.log_output = Some(format!("⏳ Bajando... {}/{}", chunk.index, chunk.total));
+                            }
+
+                            if let Some(full_data) = self.assembler.add_chunk(chunk) {
+                                if full_data.starts_with(b"FILE:") {
+                                    if let Some(separator_index) = full_data.iter().position(|&r| r == b'|') {
+                                        let name_part = &full_data[5..separator_index];
+                                        let file_content = &full_data[separator_index + 1..];
+                                        let filename = String::from_utf8_lossy(name_part);
+                                        let path = format!("downloads/{}", filename);
+                                        match File::create(&path) {
+                                            Ok(mut file) => {
+                                                let _ = file.write_all(file_content);
+                                                result.log_output = Some(format!("💾 ARCHIVO GUARDADO: {}", path));
+                                            },
+                                            Err(e) => result.log_output = Some(format!("❌ Error disco: {}", e)),
                                         }
-                                    } else {
-                                        let texto = String::from_utf8_lossy(&full_data);
-                                        result.log_output = Some(format!("📦 MENSAJE REARMADO: {}", texto));
                                     }
-                                    result.ack_to_send = Some((src, frame.header.msg_id));
+                                } else {
+                                    let texto = String::from_utf8_lossy(&full_data);
+                                    result.log_output = Some(format!("📦 MENSAJE REARMADO: {}", texto));
                                 }
+                                result.ack_to_send = Some((frame.header.src_id, frame.header.msg_id));
+                            }
+                        }
+                        // 🔇 Silenciado: igual que con el chat, se rearma y se confirma sin mostrarlo
+                        if self.blocklist.is_muted(&frame.header.src_id) { result.log_output = None; }
+                    },
+                    MessageType::KeyUpdate => {
+                        if let Ok(update) = bincode::deserialize::<KeyUpdate>(&decrypted_payload)
+                            && self.wants_key_update(&update, &frame, matches!(key_check, KeyCheck::New))
+                            && let Some(log) = self.apply_key_update(update) {
+                            result.log_output = Some(log);
+                        }
+                    },
+                    MessageType::ChannelChat if is_broadcast => {
+                        // Solo mostramos los canales en los que estamos; el resto se reenvía igual
+                        if let Ok(msg) = bincode::deserialize::<ChannelMessage>(&decrypted_payload)
+                            && let Some((name, text, is_member)) = self.channels.open(&msg, &frame.header.src_id)
+                            && !self.blocklist.is_muted(&frame.header.src_id) {
+                            let warning = if is_member { "" } else { " ⚠️(no figura en la lista de miembros)" };
+                            result.log_output = Some(format!("📢 {} {}{} dice: {}", name, self.sender_tag(&frame.header.src_id), warning, String::from_utf8_lossy(&text)));
+                        }
+                    },
+                    MessageType::ChannelKey if is_for_me => {
+                        if let Ok(invite) = bincode::deserialize::<ChannelInvite>(&decrypted_payload) {
+                            // 📮 Confirmamos siempre: el admin reenvía hasta ver nuestro ACK
+                            result.ack_to_send = Some((frame.header.src_id, frame.header.msg_id));
+                            let resent = self.channels.roster(&invite.name).is_some_and(|r| r.epoch == invite.epoch);
+                            match self.channels.apply(&invite, &self.my_id) {
+                                Ok(outcome) => result.log_output = Some(format!("{} (de {})", outcome, self.sender_tag(&frame.header.src_id))),
+                                // Un reenvío de la época que ya tenemos no es noticia
+                                Err(_) if resent => {},
+                                Err(e) => result.log_output = Some(format!("⚠️ Cambio de {} rechazado: {}", invite.name, e)),
                             }
                         }
                     },
-                    MessageType::Ack => {
-                        if is_for_me {
-                            if let Ok(original_msg_id) = bincode::deserialize::<u64>(&decrypted_payload) {
-                                result.log_output = Some(format!("✅ Confirmado (ID: {})", original_msg_id));
+                    // Solo de vecinos directos: un anuncio reenviado mentiría sobre el siguiente salto.
+                    // Y del que se presentó en esta dirección: si no, cualquiera podría repetirnos el
+                    // anuncio de otro y quedarse con todas sus rutas (un agujero negro)
+                    MessageType::RouteAdvert if frame.header.initial_ttl == 1 && self.is_direct(&frame, &src) => {
+                        if let Ok(advert) = bincode::deserialize::<RouteAdvert>(&decrypted_payload) {
+                            let cost = self.link_cost(&src);
+                            self.routes.on_advert(frame.header.src_id, src, cost, &advert);
+                        }
+                    },
+                    MessageType::RouteRequest => {
+                        if let Ok(request) = bincode::deserialize::<RouteRequest>(&decrypted_payload)
+                            && request.target == self.my_id {
+                            result.route_reply_to = Some(frame.header.src_id);
+                            self.state = State::Idle;
+                            return result; // Ya nos encontraron: no hace falta seguir inundando
+                        }
+                    },
+                    MessageType::RouteReply if is_for_me => {
+                        if let Some(route) = self.routes.next_hop(&frame.header.src_id) {
+                            result.log_output = Some(format!("🔎 Ruta a {} encontrada: {} saltos vía {}", self.sender_tag(&frame.header.src_id), route.metric, route.next_hop));
+                        }
+                    },
+                    // Como el anuncio: solo del vecino confirmado que lo firmó, y nunca se reenvía
+                    // (quien rompe rutas por él lo vuelve a avisar con su propio RERR)
+                    MessageType::RouteError if frame.header.initial_ttl == 1 && self.is_direct(&frame, &src) => {
+                        if let Ok(error) = bincode::deserialize::<RouteError>(&decrypted_payload) {
+                            self.routes.on_error(src, &error);
+                        }
+                    },
+                    // Ya quedó como vecino (add_peer): su sondeo nos dará el enlace y su Hello, la llave
+                    MessageType::Beacon if frame.header.initial_ttl == 1 => {
+                        let first_time = self.discovered.insert(frame.header.src_id);
+                        if first_time {
+                            result.log_output = Some(format!("📡 {} descubierto en la LAN ({})", self.sender_tag(&frame.header.src_id), src));
+                        }
+                    },
+                    MessageType::Ack if is_for_me => {
+                        if let Ok(original_msg_id) = bincode::deserialize::<u64>(&decrypted_payload) {
+                            result.log_output = Some(match self.channels.on_ack(original_msg_id, &frame.header.src_id) {
+                                Some(name) => format!("📮 {} confirmó la época actual de {}", self.sender_tag(&frame.header.src_id), name),
+                                None => format!("✅ Confirmado (ID: {})", original_msg_id),
+                            });
+                            // ⏱️ Un ACK directo (sin relays) mide el RTT del enlace
+                            if let Some(sent) = self.pending_acks.remove(&original_msg_id)
+                                && self.is_direct(&frame, &src)
+                                && let Some(neighbor) = self.peers.get_mut(&src) {
+                                neighbor.rtt_sample(sent.elapsed());
                             }
                         }
                     },
@@ -167,22 +575,108 @@ impl Node {
         }
 
         self.state = State::Idle;
-        if is_broadcast && frame.decrement_ttl() { 
-            result.frame_to_relay = Some(frame); 
-        } else if !is_broadcast && !is_for_me && frame.decrement_ttl() {
-             result.frame_to_relay = Some(frame); 
+        if !is_for_me && frame.header.msg_type != MessageType::RouteError && frame.forward(self.my_id) {
+            result.frame_to_relay = Some(frame);
         }
-        
+
         result
     }
-    
+
+    /// Ruta de vuelta (RREQ) o de ida (RREP) hacia el firmante, a través de quien nos la trajo
+    fn learn_route(&mut self, frame: &Frame, src: SocketAddr) {
+        let seq = match frame.header.msg_type {
+            MessageType::RouteRequest => crypto::decrypt(&frame.payload)
+                .and_then(|p| bincode::deserialize::<RouteRequest>(&p).ok()).map(|r| r.seq),
+            MessageType::RouteReply => crypto::decrypt(&frame.payload)
+                .and_then(|p| bincode::deserialize::<RouteReply>(&p).ok()).map(|r| r.seq),
+            _ => None,
+        };
+        if let Some(seq) = seq {
+            // Sin saltos solo si de verdad es el vecino; si no, al menos uno (los saltos no van firmados)
+            let hops = (frame.hops.hop_count as u32).max(if self.is_direct(frame, &src) { 0 } else { 1 });
+            self.routes.learn(frame.header.src_id, src, hops + self.link_cost(&src), seq);
+        }
+    }
+
+    /// ¿La trama viene directo de su emisor? Los saltos no van firmados (cualquiera puede
+    /// dejarlos en cero), así que además el emisor debe ser el vecino confirmado en `src`.
+    fn is_direct(&self, frame: &Frame, src: &SocketAddr) -> bool {
+        frame.hops.hop_count == 0
+            && self.peers.get(src).is_some_and(|n| n.confirmed && n.node_id == Some(frame.header.src_id))
+    }
+
+    /// Aplica un certificado de revocación/rotación. Devuelve qué mostrar si era nuevo.
+    pub fn apply_key_update(&mut self, update: KeyUpdate) -> Option<String> {
+        let old_id = node_id_from_pubkey(&update.old_pubkey);
+        let new_pubkey = update.new_pubkey;
+        if !self.revoked.insert(update) { return None; }
+
+        // Lo que hayamos acordado con la llave vieja ya no es de fiar
+        self.sessions.forget(&old_id);
+        self.peer_dh_keys.remove(&old_id);
+
+        if old_id == self.my_id {
+            return Some(format!("🚨 ALERTA: NUESTRA llave ({}) fue revocada. Rota la identidad con `identity rotate`", hex::encode(old_id)));
+        }
+        Some(match new_pubkey {
+            Some(new_pubkey) => format!("🔄 [{}] rotó su llave: ahora es [{}] (verifícalo de nuevo con /verify)",
+                hex::encode(old_id), hex::encode(node_id_from_pubkey(&new_pubkey))),
+            None => format!("🪦 La llave de [{}] fue REVOCADA: sus tramas se descartarán", hex::encode(old_id)),
+        })
+    }
+
+    /// Revocar una llave cualquiera no cuesta nada: de la malla solo guardamos (y volvemos a
+    /// anunciar) certificados de llaves que fijamos, de la nuestra o, si se exige prueba de
+    /// trabajo, los que anuncia la propia llave revocada (su trama ya pasó `has_pow`).
+    /// Una llave que se fijó con esta misma trama no cuenta como fijada de antes.
+    fn wants_key_update(&self, update: &KeyUpdate, frame: &Frame, pinned_now: bool) -> bool {
+        let old_id = node_id_from_pubkey(&update.old_pubkey);
+        let self_announced = frame.header.sender_pubkey == update.old_pubkey;
+        old_id == self.my_id
+            || (self.pow_bits > 0 && self_announced)
+            || (self.known_keys.pubkey(&old_id) == Some(update.old_pubkey) && !(self_announced && pinned_now))
+    }
+
+    /// El sello se verifica una vez por llave: después basta con la caché
+    fn has_pow(&mut self, frame: &Frame) -> bool {
+        if self.pow_bits == 0 || self.pow_ok.contains(&frame.header.sender_pubkey) { return true; }
+        if pow::stamp_bits(&frame.header.sender_pubkey, frame.header.pow_nonce) < self.pow_bits as u32 { return false; }
+        self.pow_ok.insert(frame.header.sender_pubkey);
+        true
+    }
+
+    fn flood_penalty(&mut self, src: SocketAddr, result: &mut ProcessResult) {
+        if self.rate_limiter.penalize(src, rate_limiter::FLOOD_POINTS) {
+            result.log_output = Some(format!("🚫 {} baneado por inundar la red", src));
+        }
+    }
+
+    /// Cómo se muestra un remitente en el chat (✅ si su llave fue verificada con /verify)
+    fn sender_tag(&self, node_id: &[u8; 8]) -> String {
+        let mark = if self.known_keys.is_verified(node_id) { " ✅" } else { "" };
+        format!("[{:02x?}]{}", &node_id[0..4], mark)
+    }
+
+    /// Guarda la llave X25519 de un nodo. Devuelve true si es nueva o cambió.
+    fn learn_dh_key(&mut self, peer_id: [u8; 8], peer_dh_pubkey: [u8; 32]) -> bool {
+        // Rechazamos puntos de orden bajo (el secreto sería predecible)
+        if !self.dh_secret.diffie_hellman(&PublicKey::from(peer_dh_pubkey)).was_contributory() { return false; }
+        self.peer_dh_keys.insert(peer_id, peer_dh_pubkey) != Some(peer_dh_pubkey)
+    }
+
     fn verify_signature(&self, frame: &Frame) -> bool {
         let pubkey_bytes = frame.header.sender_pubkey;
         let verifying_key = match VerifyingKey::from_bytes(&pubkey_bytes) { Ok(k) => k, Err(_) => return false };
         let signature_bytes: [u8; 64] = match frame.signature.as_slice().try_into() { Ok(b) => b, Err(_) => return false };
         let signature = Signature::from_bytes(&signature_bytes);
-        let mut h = frame.header.clone(); h.ttl = 0; h.flags = 0; 
-        let mut d = bincode::serialize(&h).unwrap(); d.extend_from_slice(&frame.payload);
-        verifying_key.verify(&d, &signature).is_ok()
+        verifying_key.verify(&frame.signable_bytes(), &signature).is_ok()
     }
-}
\ No newline at end of file
+}
+
+/// Material estático para iniciar una sesión de DM: acuerdo X25519 con la llave del Hello
+fn peer_keys(my_id: [u8; 8], my_static: &StaticSecret, peer_dh_keys: &HashMap<[u8; 8], [u8; 32]>, peer_id: [u8; 8]) -> Option<PeerKeys> {
+    let peer_static = *peer_dh_keys.get(&peer_id)?;
+    let shared = my_static.diffie_hellman(&PublicKey::from(peer_static));
+    let pair_key = crypto::derive_pair_key(shared.as_bytes(), &my_id, &peer_id);
+    Some(PeerKeys { my_id, peer_id, peer_static, pair_key })
+}
@@ -0,0 +1,65 @@
+use crate::protocol::PeerEntry;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+// 🤝 Intercambio de vecinos. Lo que otro nodo nos cuenta no entra directo a `peers`:
+// queda como candidato y solo se vuelve vecino cuando responde a nuestro sondeo con
+// una trama firmada, así una lista inventada no nos llena de direcciones falsas.
+pub const PEER_LIST_INTERVAL: Duration = Duration::from_secs(30);
+pub const MAX_PEER_LIST: usize = 16;
+pub const MAX_AGE_SECS: u32 = 60; // Vecinos más viejos no se anuncian ni se aceptan
+const MAX_CANDIDATES: usize = 64;
+const MAX_PER_SOURCE: usize = 8; // Un solo nodo no puede ocupar toda la lista de candidatos
+const MAX_PROBES: u8 = 3; // Sondeos sin respuesta antes de olvidar un candidato
+
+struct Candidate {
+    via: [u8; 8], // Quién nos habló de él
+    probes: u8,
+}
+
+pub struct Candidates {
+    candidates: HashMap<SocketAddr, Candidate>,
+}
+
+impl Candidates {
+    pub fn new() -> Self {
+        Self { candidates: HashMap::new() }
+    }
+
+    /// Agrega lo que anunció `via` (entradas ya filtradas). Devuelve cuántos candidatos nuevos hay.
+    pub fn offer<'a>(&mut self, via: [u8; 8], entries: impl Iterator<Item = &'a PeerEntry>) -> usize {
+        let mut from_source = self.candidates.values().filter(|c| c.via == via).count();
+        let mut added = 0;
+        for entry in entries {
+            if from_source >= MAX_PER_SOURCE || self.candidates.len() >= MAX_CANDIDATES { break; }
+            if self.candidates.contains_key(&entry.addr) { continue; }
+            self.candidates.insert(entry.addr, Candidate { via, probes: 0 });
+            from_source += 1;
+            added += 1;
+        }
+        added
+    }
+
+    /// Una dirección de arranque (línea de comandos, config.json o la libreta): sin límite por fuente
+    pub fn add(&mut self, addr: SocketAddr) -> bool {
+        if self.candidates.len() >= MAX_CANDIDATES || self.candidates.contains_key(&addr) { return false; }
+        self.candidates.insert(addr, Candidate { via: [0; 8], probes: 0 });
+        true
+    }
+
+    /// Respondió (ya es vecino): deja de ser candidato
+    pub fn remove(&mut self, addr: &SocketAddr) {
+        self.candidates.remove(addr);
+    }
+
+    /// Direcciones a las que mandar un sondeo ahora. Las que ya se probaron MAX_PROBES veces se olvidan.
+    pub fn probe(&mut self) -> Vec<SocketAddr> {
+        self.candidates.retain(|_, c| c.probes < MAX_PROBES);
+        self.candidates.iter_mut().map(|(addr, c)| { c.probes += 1; *addr }).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+}
@@ -0,0 +1,62 @@
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// ⛏️ Sello de prueba de trabajo de la identidad: un nonce tal que
+// SHA-256(etiqueta | llave pública | nonce) empieza con N bits en cero.
+// Crear una identidad "cuesta" 2^N hashes, así que inventar miles para inundar
+// la malla deja de ser gratis. Viaja en cada Header (firmado).
+pub const MAX_POW_BITS: u8 = 32;
+
+// Sello de nuestra identidad (se calcula una vez al arrancar)
+static STAMP: OnceLock<u64> = OnceLock::new();
+
+#[derive(Serialize, Deserialize)]
+struct SavedStamp {
+    pubkey_hex: String,
+    nonce: u64,
+}
+
+/// Cuántos bits en cero tiene el sello de esta llave
+pub fn stamp_bits(pubkey: &[u8; 32], nonce: u64) -> u32 {
+    let mut hasher = Sha256::new();
+    hasher.update(b"EMBER-POW-v1");
+    hasher.update(pubkey);
+    hasher.update(nonce.to_le_bytes());
+    let digest = hasher.finalize();
+    let mut bits = 0;
+    for byte in digest {
+        bits += byte.leading_zeros();
+        if byte != 0 { break; }
+    }
+    bits
+}
+
+/// Reutiliza el sello guardado en el perfil si alcanza; si no, calcula uno nuevo
+pub fn load_or_mine(path: &Path, pubkey: &[u8; 32], bits: u8) -> u64 {
+    let saved = fs::read_to_string(path).ok()
+        .and_then(|json| serde_json::from_str::<SavedStamp>(&json).ok())
+        .filter(|s| s.pubkey_hex == hex::encode(pubkey));
+    if let Some(saved) = &saved
+        && stamp_bits(pubkey, saved.nonce) >= bits as u32 {
+        return saved.nonce;
+    }
+
+    println!("⛏️ Calculando el sello de prueba de trabajo ({} bits)...", bits);
+    let nonce = (0u64..).find(|n| stamp_bits(pubkey, *n) >= bits as u32).unwrap();
+    let stamp = SavedStamp { pubkey_hex: hex::encode(pubkey), nonce };
+    if let Ok(json) = serde_json::to_string_pretty(&stamp) {
+        let _ = fs::write(path, json);
+    }
+    nonce
+}
+
+pub fn set_stamp(nonce: u64) {
+    let _ = STAMP.set(nonce);
+}
+
+pub fn stamp() -> u64 {
+    STAMP.get().copied().unwrap_or(0)
+}
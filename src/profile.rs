@@ -0,0 +1,116 @@
+use serde::de::DeserializeOwned;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// 🗂️ Perfil: una identidad con todo su estado (llaves fijadas, sesiones...).
+/// Vive en `<datos>/profiles/<nombre>/`, sin importar el puerto UDP que se use.
+pub struct Profile {
+    pub name: String,
+    pub dir: PathBuf,
+}
+
+impl Profile {
+    /// Carpeta de datos: --data-dir > EMBER_HOME > carpeta de datos del sistema
+    pub fn data_dir(override_dir: Option<PathBuf>) -> PathBuf {
+        override_dir
+            .or_else(|| env::var_os("EMBER_HOME").map(PathBuf::from))
+            .or_else(|| dirs::data_dir().map(|d| d.join("ember")))
+            .unwrap_or_else(|| PathBuf::from("ember-data"))
+    }
+
+    /// Abre (creando si hace falta) la carpeta de un perfil
+    pub fn open(data_dir: &Path, name: &str) -> Result<Self, String> {
+        let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(format!("Nombre de perfil inválido: '{}' (usa letras, números, - o _)", name));
+        }
+        let dir = data_dir.join("profiles").join(name);
+        fs::create_dir_all(&dir).map_err(|e| format!("No se pudo crear {}: {}", dir.display(), e))?;
+        Ok(Self { name: name.to_string(), dir })
+    }
+
+    /// Perfiles existentes en la carpeta de datos
+    pub fn list(data_dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(data_dir.join("profiles")).into_iter().flatten().flatten()
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .collect();
+        names.sort();
+        names
+    }
+
+    pub fn identity_path(&self) -> PathBuf { self.dir.join("identity.json") }
+    pub fn known_keys_path(&self) -> PathBuf { self.dir.join("known_keys.json") }
+    pub fn sessions_dir(&self) -> PathBuf { self.dir.join("sessions") }
+    pub fn revoked_path(&self) -> PathBuf { self.dir.join("revoked.json") }
+    pub fn replay_path(&self) -> PathBuf { self.dir.join("replay.json") }
+    pub fn pow_path(&self) -> PathBuf { self.dir.join("pow.json") }
+    pub fn blocklist_path(&self) -> PathBuf { self.dir.join("blocklist.json") }
+    pub fn channels_path(&self) -> PathBuf { self.dir.join("channels.json") }
+    pub fn peers_path(&self) -> PathBuf { self.dir.join("peers.json") }
+    pub fn config_path(&self) -> PathBuf { self.dir.join("config.json") }
+
+    /// 🔄 Adopta los archivos de la época en que la identidad dependía del puerto
+    /// (`identity_<PUERTO>.json` y compañía en la carpeta actual), si el perfil está vacío.
+    pub fn adopt_legacy_files(&self, port: u16) -> Option<String> {
+        let legacy_identity = PathBuf::from(format!("identity_{}.json", port));
+        if self.identity_path().exists() || !legacy_identity.exists() { return None; }
+
+        fs::rename(&legacy_identity, self.identity_path())
+            .or_else(|_| fs::copy(&legacy_identity, self.identity_path()).and_then(|_| fs::remove_file(&legacy_identity)))
+            .ok()?;
+        let _ = fs::rename(format!("known_keys_{}.json", port), self.known_keys_path());
+        let _ = fs::rename(format!("sessions_{}", port), self.sessions_dir());
+        Some(format!("📦 {} adoptada por el perfil '{}' ({})", legacy_identity.display(), self.name, self.dir.display()))
+    }
+}
+
+/// 💾 Escribe un archivo del perfil sin dejarlo a medias: primero a un temporal y después
+/// se renombra encima. Un corte de luz deja el viejo o el nuevo, nunca uno roto.
+pub fn write_atomic(path: &Path, contents: &str) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// 🔒 Como write_atomic, pero el archivo queda legible solo por su dueño (0600 en unix).
+/// Para lo que guarda secretos en claro, como las sesiones de DM.
+pub fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    // Un temporal viejo conservaría sus permisos: se crea siempre de cero
+    let _ = fs::remove_file(&tmp);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// Crea (o cierra, si ya existía) una carpeta a la que solo entra su dueño (0700 en unix)
+pub fn private_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    fs::set_permissions(dir, std::os::unix::fs::PermissionsExt::from_mode(0o700))?;
+    Ok(())
+}
+
+/// Lee un JSON del perfil. Si no existe, vacío; si está dañado, error: pisarlo con uno
+/// vacío borraría en silencio lo que guardaba (p. ej. todas las llaves fijadas).
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T, String> {
+    match fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json).map_err(|e| format!(
+            "{} está dañado ({}). No lo pisamos: restáuralo de un respaldo o muévelo a un lado y vuelve a arrancar",
+            path.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(format!("No se pudo leer {}: {}", path.display(), e)),
+    }
+}
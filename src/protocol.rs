@@ -1,56 +1,154 @@
+use crate::neighbor::Echo;
 use serde::{Serialize, Deserialize};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub const MAGIC_BYTES: u16 = 0xEB01; 
-pub const CURRENT_VERSION: u8 = 1;
+pub const CURRENT_VERSION: u8 = 9;
+pub const FLOOD_TTL: u8 = 6; // Difusión y descubrimiento (Hello, chat general...)
+pub const MAX_TTL: u8 = 16;
+pub const HELLO_INTERVAL: Duration = Duration::from_secs(30); // La llave de DM casi no cambia
 // ID especial para "A todos" (Broadcast)
 pub const BROADCAST_ID: [u8; 8] = [0; 8];
+// 🔑 Hello dirigido a un nodo lejano: "mándame el tuyo" (para tener su llave de DM)
+pub const FLAG_WANT_HELLO: u8 = 0x01;
 
 #[repr(u8)]
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MessageType {
     Hello = 0x01,     
     PeerList = 0x02,  
     Chat = 0x03,      
     FileChunk = 0x04, 
     Ack = 0x05,       
+    KeyUpdate = 0x06, // Revocación o rotación de una llave (se inunda como la difusión)
+    ChannelChat = 0x07, // Mensaje de un canal de grupo (ver channels.rs)
+    ChannelKey = 0x08,  // Invitación con la llave de un canal (solo por DM)
+    RouteAdvert = 0x09, // Tabla de rutas para los vecinos (ver routing.rs)
+    RouteRequest = 0x0A, // 🔎 Búsqueda de ruta bajo demanda (se inunda)
+    RouteReply = 0x0B,   // Respuesta del destino por la ruta de vuelta
+    RouteError = 0x0C,   // Rutas rotas por la caída de un vecino
+    Beacon = 0x0D,       // 📡 "Aquí estoy" por multicast a la LAN (TTL 1)
+    LinkProbe = 0x0E,    // 📶 Ecos para medir el enlace con los vecinos (TTL 1)
     Unknown = 0xFF,   
 }
 
+/// ✍️ Cabecera firmada: nada de aquí puede cambiar en el camino
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Header {
     pub magic: u16,
     pub version: u8,
+    pub network_id: [u8; 8], // Huella de la clave de red (ver crypto::network_id)
     pub msg_type: MessageType,
-    pub ttl: u8,
+    pub initial_ttl: u8, // Saltos que el emisor autorizó (tope de `Hops::ttl`)
     pub flags: u8,
     pub msg_id: u64,
+    pub timestamp_ms: u64, // Hora de envío (UNIX, ms): ver replay_cache
     pub src_id: [u8; 8],
     pub dest_id: [u8; 8], // 👈 NUEVO CAMPO: ¿Para quién es esto?
     pub sender_pubkey: [u8; 32],
+    pub pow_nonce: u64, // Sello de prueba de trabajo de la identidad (ver pow.rs)
     pub payload_len: u16,
 }
 
+pub fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// Contenido (cifrado con la clave de red) de un mensaje Hello
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hello {
+    pub dh_pubkey: [u8; 32], // Llave X25519 para acordar la llave de los DMs
+}
+
+/// 📶 Sondeo de enlace, solo para los vecinos directos (TTL 1, nunca se reenvía).
+/// Los ecos solo les sirven a ellos: no viajan en el Hello para no inundarlos por la malla.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinkProbe {
+    pub echoes: Vec<Echo>, // Eco del último sondeo de cada vecino (RTT y tasa de entrega)
+}
+
+/// 🤝 Vecinos directos que anunciamos a los nuestros (TTL 1, nunca se reenvía).
+/// Va firmado como toda trama: quien lo recibe sabe quién se los presentó.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PeerList {
+    pub entries: Vec<PeerEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PeerEntry {
+    pub addr: SocketAddr,
+    pub node_id: [u8; 8],
+    pub age_secs: u32, // Hace cuánto lo escuchamos
+}
+
+/// 🪦 Certificado de revocación (new_pubkey = None) o de rotación de una llave.
+/// Lo firma la llave vieja; en una rotación también la nueva, para probar que existe.
+/// Se valida solo, así que cualquier nodo puede volver a anunciarlo.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyUpdate {
+    pub old_pubkey: [u8; 32],
+    pub new_pubkey: Option<[u8; 32]>,
+    pub issued_at: u64, // Segundos UNIX
+    pub old_signature: Vec<u8>,
+    pub new_signature: Vec<u8>,
+}
+
+/// 🔁 Campos que cambian en cada salto (fuera de la firma).
+/// Siempre se cumple ttl + hop_count == initial_ttl y path.len() == hop_count, así que
+/// nadie puede dar más saltos que los que firmó el emisor. Pero nada impide volver a
+/// {ttl: initial_ttl, hop_count: 0, path: []}: cualquiera que la haya recibido puede
+/// repetirla como si fuera el emisor. hop_count == 0 es un indicio, no una prueba de
+/// que quien nos la entrega es el emisor (ver `Node::is_direct`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hops {
+    pub ttl: u8,
+    pub hop_count: u8,
+    pub path: Vec<[u8; 8]>, // IDs de los relays, en orden (informativo: no va firmado)
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Frame {
     pub header: Header,
+    pub hops: Hops,
     pub payload: Vec<u8>,
     pub signature: Vec<u8>,
 }
 
 impl Frame {
+    /// Trama nueva (sin firmar todavía) con todos los saltos disponibles
+    pub fn new(header: Header, payload: Vec<u8>) -> Self {
+        let hops = Hops { ttl: header.initial_ttl, hop_count: 0, path: Vec::new() };
+        Self { header, hops, payload, signature: Vec::new() }
+    }
+
+    /// Lo que firma el emisor: la cabecera completa y el contenido (nunca `hops`)
+    pub fn signable_bytes(&self) -> Vec<u8> {
+        let mut data = bincode::serialize(&self.header).unwrap();
+        data.extend_from_slice(&self.payload);
+        data
+    }
+
     pub fn is_valid_structure(&self) -> bool {
         if self.header.magic != MAGIC_BYTES { return false; }
+        if self.header.version != CURRENT_VERSION { return false; }
         if self.header.payload_len as usize != self.payload.len() { return false; }
-        if self.header.ttl == 0 { return false; }
+        if self.header.initial_ttl == 0 || self.header.initial_ttl > MAX_TTL { return false; }
+        // La parte mutable debe cuadrar con el TTL firmado
+        let hops = &self.hops;
+        if hops.ttl == 0 || hops.ttl > self.header.initial_ttl { return false; }
+        if hops.ttl as u16 + hops.hop_count as u16 != self.header.initial_ttl as u16 { return false; }
+        if hops.path.len() != hops.hop_count as usize { return false; }
         true
     }
 
-    pub fn decrement_ttl(&mut self) -> bool {
-        if self.header.ttl > 0 {
-            self.header.ttl -= 1;
-            true
-        } else {
-            false
-        }
+    /// Prepara la trama para reenviarla: gasta un salto y anota al relay.
+    /// Devuelve false si ya no le quedan saltos.
+    pub fn forward(&mut self, relay_id: [u8; 8]) -> bool {
+        if self.hops.ttl <= 1 { return false; }
+        self.hops.ttl -= 1;
+        self.hops.hop_count += 1;
+        self.hops.path.push(relay_id);
+        true
     }
 }
\ No newline at end of file
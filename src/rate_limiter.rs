@@ -1,27 +1,137 @@
+use crate::protocol::MessageType;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::time::{Duration, Instant};
 
-pub struct RateLimiter {
+// Un vecino reenvía tráfico de muchos nodos: su cubeta es más grande que la de un solo emisor
+const ADDR_MULTIPLIER: f64 = 4.0;
+// 🚩 Puntaje de mal comportamiento (por dirección): baja 1 punto por segundo
+const INVALID_SIGNATURE_POINTS: f64 = 10.0;
+pub const FLOOD_POINTS: f64 = 1.0;
+const BAN_THRESHOLD: f64 = 50.0;
+const BAN_DURATION: Duration = Duration::from_secs(5 * 60);
+const IDLE_BUCKET: Duration = Duration::from_secs(60);
+
+/// (ráfaga máxima, tramas por segundo sostenidas) para un emisor
+fn budget(msg_type: MessageType) -> (f64, f64) {
+    match msg_type {
+        MessageType::Hello => (5.0, 1.0),
+        MessageType::PeerList => (5.0, 1.0),
+        MessageType::Chat => (20.0, 5.0),
+        MessageType::FileChunk => (200.0, 50.0),
+        MessageType::Ack => (20.0, 5.0),
+        MessageType::KeyUpdate => (20.0, 1.0),
+        MessageType::ChannelChat => (20.0, 5.0),
+        MessageType::ChannelKey => (10.0, 1.0),
+        MessageType::RouteAdvert => (5.0, 1.0),
+        MessageType::RouteRequest => (10.0, 1.0),
+        MessageType::RouteReply => (10.0, 1.0),
+        MessageType::RouteError => (10.0, 1.0),
+        MessageType::Beacon => (5.0, 1.0),
+        MessageType::LinkProbe => (5.0, 1.0),
+        MessageType::Unknown => (1.0, 0.1),
+    }
+}
+
+/// 🪣 Cubeta de fichas: se llena a `rate` por segundo hasta `capacity`
+struct TokenBucket {
+    tokens: f64,
     last: Instant,
-    min_interval: Duration,
+}
+
+impl TokenBucket {
+    fn take(&mut self, capacity: f64, rate: f64, now: Instant) -> bool {
+        self.tokens = (self.tokens + now.duration_since(self.last).as_secs_f64() * rate).min(capacity);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct Score {
+    points: f64,
+    last: Instant,
+}
+
+pub struct RateLimiter {
+    by_src: HashMap<([u8; 8], MessageType), TokenBucket>,
+    by_addr: HashMap<(SocketAddr, MessageType), TokenBucket>,
+    scores: HashMap<SocketAddr, Score>,
+    bans: HashMap<SocketAddr, Instant>, // Dirección -> hasta cuándo
 }
 
 impl RateLimiter {
-    /// Permite 1 acción cada X ms
     pub fn new() -> Self {
         Self {
-            last: Instant::now() - Duration::from_secs(1),
-            min_interval: Duration::from_millis(300),
+            by_src: HashMap::new(),
+            by_addr: HashMap::new(),
+            scores: HashMap::new(),
+            bans: HashMap::new(),
         }
     }
 
-    /// Devuelve true si se permite la acción
-    pub fn allow(&mut self) -> bool {
+    /// Cubeta del vecino que nos entregó la trama (antes de gastar CPU en la firma)
+    pub fn allow_addr(&mut self, addr: SocketAddr, msg_type: MessageType) -> bool {
+        let (capacity, rate) = budget(msg_type);
+        Self::take(&mut self.by_addr, (addr, msg_type), capacity * ADDR_MULTIPLIER, rate * ADDR_MULTIPLIER)
+    }
+
+    /// Cubeta del emisor original (solo con la firma ya verificada: nadie puede gastar la de otro)
+    pub fn allow_src(&mut self, src_id: [u8; 8], msg_type: MessageType) -> bool {
+        let (capacity, rate) = budget(msg_type);
+        Self::take(&mut self.by_src, (src_id, msg_type), capacity, rate)
+    }
+
+    fn take<K: std::hash::Hash + Eq>(buckets: &mut HashMap<K, TokenBucket>, key: K, capacity: f64, rate: f64) -> bool {
         let now = Instant::now();
-        if now.duration_since(self.last) >= self.min_interval {
-            self.last = now;
-            true
-        } else {
-            false
+        buckets.entry(key)
+            .or_insert(TokenBucket { tokens: capacity, last: now })
+            .take(capacity, rate, now)
+    }
+
+    pub fn is_banned(&mut self, addr: &SocketAddr) -> bool {
+        match self.bans.get(addr) {
+            Some(until) if Instant::now() < *until => true,
+            Some(_) => { self.bans.remove(addr); false },
+            None => false,
         }
     }
+
+    pub fn penalize_invalid_signature(&mut self, addr: SocketAddr) -> bool {
+        self.penalize(addr, INVALID_SIGNATURE_POINTS)
+    }
+
+    /// Suma puntos a una dirección. Devuelve true si con esto queda baneada.
+    pub fn penalize(&mut self, addr: SocketAddr, points: f64) -> bool {
+        let now = Instant::now();
+        let score = self.scores.entry(addr).or_insert(Score { points: 0.0, last: now });
+        score.points = (score.points - now.duration_since(score.last).as_secs_f64()).max(0.0) + points;
+        score.last = now;
+        if score.points < BAN_THRESHOLD { return false; }
+        self.scores.remove(&addr);
+        self.bans.insert(addr, now + BAN_DURATION);
+        true
+    }
+
+    /// Baneos vigentes y cuánto les queda
+    pub fn bans(&self) -> Vec<(SocketAddr, Duration)> {
+        let now = Instant::now();
+        self.bans.iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(addr, until)| (*addr, *until - now))
+            .collect()
+    }
+
+    /// Olvida cubetas y puntajes inactivos (ya estarían llenas / en cero)
+    pub fn cleanup(&mut self) {
+        let now = Instant::now();
+        self.by_src.retain(|_, b| now.duration_since(b.last) < IDLE_BUCKET);
+        self.by_addr.retain(|_, b| now.duration_since(b.last) < IDLE_BUCKET);
+        self.scores.retain(|_, s| s.points > now.duration_since(s.last).as_secs_f64());
+        self.bans.retain(|_, until| *until > now);
+    }
 }
@@ -1,6 +1,10 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::PathBuf;
 
 const MAX_CACHE: usize = 2048;
+// ⏰ Tramas con una marca de tiempo más lejana que esto (hacia atrás o adelante) se rechazan
+pub const MAX_CLOCK_SKEW_MS: u64 = 5 * 60 * 1000;
 
 #[derive(Hash, Eq, PartialEq, Clone)]
 pub struct ReplayKey {
@@ -8,34 +12,142 @@ pub struct ReplayKey {
     pub msg_id: [u8; 8],
 }
 
+/// Anti-repetición en dos capas:
+/// - dentro de la ventana de tiempo, recordamos cada (emisor, msg_id);
+/// - por emisor, un "piso": nada con marca de tiempo <= piso se acepta. El piso sube
+///   cuando olvidamos entradas por falta de espacio y, al arrancar, es la marca más
+///   alta que vimos antes de apagarnos (se guarda en disco).
 pub struct ReplayCache {
+    path: PathBuf,
     set: HashSet<ReplayKey>,
-    order: VecDeque<ReplayKey>,
+    order: VecDeque<(ReplayKey, u64)>,
+    floors: HashMap<[u8; 8], u64>,
+    high_water: HashMap<[u8; 8], u64>,
+    dirty: bool,
 }
 
 impl ReplayCache {
-    pub fn new() -> Self {
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let saved: HashMap<String, u64> = fs::read_to_string(&path).ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let high_water: HashMap<[u8; 8], u64> = saved.into_iter()
+            .filter_map(|(id_hex, ts)| Some((hex::decode(id_hex).ok()?.try_into().ok()?, ts)))
+            .collect();
         Self {
+            path,
             set: HashSet::new(),
             order: VecDeque::new(),
+            floors: high_water.clone(),
+            high_water,
+            dirty: false,
         }
     }
 
-    /// Devuelve true si YA fue visto
-    pub fn seen(&mut self, key: ReplayKey) -> bool {
+    /// ¿La marca de tiempo cae dentro de la ventana aceptada?
+    pub fn in_window(timestamp_ms: u64, now_ms: u64) -> bool {
+        timestamp_ms.abs_diff(now_ms) <= MAX_CLOCK_SKEW_MS
+    }
+
+    /// Devuelve true si YA fue visto (o es más viejo que el piso del emisor).
+    /// Si es nuevo, lo registra.
+    pub fn seen(&mut self, key: ReplayKey, timestamp_ms: u64, now_ms: u64) -> bool {
+        if self.floors.get(&key.sender).is_some_and(|floor| timestamp_ms <= *floor) {
+            return true;
+        }
         if self.set.contains(&key) {
             return true;
         }
 
+        let high = self.high_water.entry(key.sender).or_insert(0);
+        if timestamp_ms > *high { *high = timestamp_ms; self.dirty = true; }
         self.set.insert(key.clone());
-        self.order.push_back(key);
+        self.order.push_back((key, timestamp_ms));
 
-        if self.order.len() > MAX_CACHE {
-            if let Some(old) = self.order.pop_front() {
-                self.set.remove(&old);
-            }
+        // Lo que ya salió de la ventana se rechaza por tiempo: no hace falta recordarlo
+        while let Some((_, ts)) = self.order.front()
+            && now_ms.saturating_sub(*ts) > MAX_CLOCK_SKEW_MS {
+            let (old, _) = self.order.pop_front().unwrap();
+            self.set.remove(&old);
+        }
+        // Sin espacio: olvidamos lo más viejo, pero subimos el piso de su emisor
+        while self.order.len() > MAX_CACHE
+            && let Some((old, ts)) = self.order.pop_front() {
+            let floor = self.floors.entry(old.sender).or_insert(0);
+            *floor = (*floor).max(ts);
+            self.set.remove(&old);
         }
 
         false
     }
+
+    /// 💾 Guarda las marcas más altas por emisor (solo las que aún están en la ventana)
+    pub fn save(&mut self, now_ms: u64) {
+        if !self.dirty { return; }
+        self.high_water.retain(|_, ts| now_ms.saturating_sub(*ts) <= MAX_CLOCK_SKEW_MS);
+        self.floors.retain(|_, ts| now_ms.saturating_sub(*ts) <= MAX_CLOCK_SKEW_MS);
+        let saved: HashMap<String, u64> = self.high_water.iter().map(|(id, ts)| (hex::encode(id), *ts)).collect();
+        if let Ok(json) = serde_json::to_string_pretty(&saved) {
+            let _ = fs::write(&self.path, json);
+        }
+        self.dirty = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000_000;
+
+    fn key(sender: u8, n: u64) -> ReplayKey {
+        ReplayKey { sender: [sender; 8], msg_id: n.to_be_bytes() }
+    }
+
+    fn temp_path(tag: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ember-replay-{}-{}.json", std::process::id(), tag));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn floor_survives_restart() {
+        let path = temp_path("restart");
+        let mut cache = ReplayCache::load(&path);
+        assert!(!cache.seen(key(1, 1), NOW, NOW));
+        cache.save(NOW);
+
+        // Tras reiniciar ya no recordamos el msg_id, pero sí la marca más alta del emisor
+        let mut cache = ReplayCache::load(&path);
+        assert!(cache.seen(key(1, 1), NOW, NOW));
+        assert!(cache.seen(key(1, 2), NOW - 1, NOW));
+        assert!(!cache.seen(key(1, 3), NOW + 1, NOW));
+        assert!(!cache.seen(key(2, 1), NOW, NOW));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn eviction_raises_the_sender_floor() {
+        let path = temp_path("evict");
+        let mut cache = ReplayCache::load(&path);
+        for n in 0..=MAX_CACHE as u64 {
+            assert!(!cache.seen(key(1, n), NOW - 1000 + n, NOW));
+        }
+        // La primera entrada se olvidó: su marca pasó a ser el piso del emisor
+        assert!(cache.seen(key(1, 0), NOW - 1000, NOW));
+        assert!(cache.seen(key(1, 9999), NOW - 1000, NOW));
+        assert!(!cache.seen(key(1, 10_000), NOW - 999 + MAX_CACHE as u64, NOW));
+        // Otros emisores no heredan ese piso
+        assert!(!cache.seen(key(2, 0), NOW - 1000, NOW));
+    }
+
+    #[test]
+    fn window_bounds() {
+        assert!(ReplayCache::in_window(NOW, NOW));
+        assert!(ReplayCache::in_window(NOW - MAX_CLOCK_SKEW_MS, NOW));
+        assert!(ReplayCache::in_window(NOW + MAX_CLOCK_SKEW_MS, NOW));
+        assert!(!ReplayCache::in_window(NOW - MAX_CLOCK_SKEW_MS - 1, NOW));
+        assert!(!ReplayCache::in_window(NOW + MAX_CLOCK_SKEW_MS + 1, NOW));
+    }
 }
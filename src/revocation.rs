@@ -0,0 +1,113 @@
+use crate::identity::Identity;
+use crate::profile;
+use crate::protocol::KeyUpdate;
+use ed25519_dalek::{Signature, Signer, Verifier, VerifyingKey};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Lo que firman ambas llaves: [etiqueta | vieja | nueva (o ceros) | fecha]
+fn statement(old_pubkey: &[u8; 32], new_pubkey: Option<&[u8; 32]>, issued_at: u64) -> Vec<u8> {
+    let mut data = b"EMBER-KEY-UPDATE-v1".to_vec();
+    data.extend_from_slice(old_pubkey);
+    data.extend_from_slice(new_pubkey.unwrap_or(&[0u8; 32]));
+    data.extend_from_slice(&issued_at.to_le_bytes());
+    data
+}
+
+impl KeyUpdate {
+    /// Revocación pura: la llave no debe volver a usarse
+    pub fn revoke(old: &Identity) -> Self {
+        Self::issue(old, None)
+    }
+
+    /// Rotación: la llave vieja señala a la nueva y ambas firman
+    pub fn rotate(old: &Identity, new: &Identity) -> Self {
+        Self::issue(old, Some(new))
+    }
+
+    fn issue(old: &Identity, new: Option<&Identity>) -> Self {
+        let issued_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let old_pubkey = old.verify.to_bytes();
+        let new_pubkey = new.map(|n| n.verify.to_bytes());
+        let data = statement(&old_pubkey, new_pubkey.as_ref(), issued_at);
+        Self {
+            old_pubkey,
+            new_pubkey,
+            issued_at,
+            old_signature: old.signing.sign(&data).to_bytes().to_vec(),
+            new_signature: new.map(|n| n.signing.sign(&data).to_bytes().to_vec()).unwrap_or_default(),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let data = statement(&self.old_pubkey, self.new_pubkey.as_ref(), self.issued_at);
+        let signed_by = |pubkey: &[u8; 32], sig: &[u8]| -> bool {
+            let Ok(key) = VerifyingKey::from_bytes(pubkey) else { return false };
+            let Ok(sig_bytes) = <[u8; 64]>::try_from(sig) else { return false };
+            key.verify(&data, &Signature::from_bytes(&sig_bytes)).is_ok()
+        };
+        if !signed_by(&self.old_pubkey, &self.old_signature) { return false; }
+        match &self.new_pubkey {
+            Some(new_pubkey) => new_pubkey != &self.old_pubkey && signed_by(new_pubkey, &self.new_signature),
+            None => true,
+        }
+    }
+}
+
+// Cuántos certificados guardamos como mucho, y cuáles se vuelven a anunciar (los recientes)
+const MAX_UPDATES: usize = 1024;
+const ANNOUNCE_MAX_AGE_SECS: u64 = 7 * 24 * 3600;
+const MAX_ANNOUNCED: usize = 32;
+
+/// 🪦 Llaves revocadas (llave pública en hex -> certificado), guardadas en el perfil.
+/// Los certificados se conservan completos para volver a anunciarlos a la malla.
+pub struct RevokedKeys {
+    path: PathBuf,
+    updates: HashMap<String, KeyUpdate>,
+}
+
+impl RevokedKeys {
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, String> {
+        let path = path.into();
+        let updates = profile::load_json(&path)?;
+        Ok(Self { path, updates })
+    }
+
+    pub fn is_revoked(&self, pubkey: &[u8; 32]) -> bool {
+        self.updates.contains_key(&hex::encode(pubkey))
+    }
+
+    /// Guarda un certificado válido. Devuelve true si cambió algo (llave nueva o
+    /// una revocación pura que reemplaza a una rotación: revocar siempre gana).
+    pub fn insert(&mut self, update: KeyUpdate) -> bool {
+        if !update.is_valid() { return false; }
+        let key = hex::encode(update.old_pubkey);
+        let replaces = match self.updates.get(&key) {
+            None => self.updates.len() < MAX_UPDATES,
+            Some(existing) => existing.new_pubkey.is_some() && update.new_pubkey.is_none(),
+        };
+        if replaces {
+            self.updates.insert(key, update);
+            self.save();
+        }
+        replaces
+    }
+
+    /// Los certificados a volver a anunciar: solo los recientes, los más nuevos primero
+    pub fn recent(&self) -> Vec<&KeyUpdate> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let mut recent: Vec<&KeyUpdate> = self.updates.values()
+            .filter(|u| now.saturating_sub(u.issued_at) <= ANNOUNCE_MAX_AGE_SECS)
+            .collect();
+        recent.sort_by_key(|u| std::cmp::Reverse(u.issued_at));
+        recent.truncate(MAX_ANNOUNCED);
+        recent
+    }
+
+    fn save(&self) {
+        if let Ok(json) = serde_json::to_string_pretty(&self.updates) {
+            let _ = profile::write_atomic(&self.path, &json);
+        }
+    }
+}
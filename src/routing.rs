@@ -0,0 +1,220 @@
+use crate::protocol::{self, Frame};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+// 🧭 Vector de distancias al estilo DSDV: cada nodo anuncia a sus vecinos
+// (destino, saltos, secuencia) y el número de secuencia, que solo el destino
+// genera (par) o quien detecta la caída (impar), evita los bucles.
+// En modo bajo demanda no hay anuncios: las mismas rutas se llenan con RREQ/RREP.
+pub const INFINITY: u32 = 16;
+pub const ADVERT_INTERVAL: Duration = Duration::from_secs(10);
+const ROUTE_TIMEOUT: Duration = Duration::from_secs(30);
+// 🔎 Modo bajo demanda (estilo AODV): cuánto esperamos una respuesta y cuántas tramas guardamos mientras
+pub const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_PENDING: usize = 256;
+
+/// Contenido (cifrado con la clave de red) de un RouteAdvert. Solo va a los vecinos (TTL 1).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RouteAdvert {
+    pub seq: u64, // Secuencia propia del emisor
+    pub routes: Vec<AdvertEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AdvertEntry {
+    pub dest: [u8; 8],
+    pub metric: u32,
+    pub seq: u64,
+}
+
+/// 🔎 RREQ (difusión): "¿quién llega a `target`?". El origen es el src_id de la trama.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RouteRequest {
+    pub target: [u8; 8],
+    pub seq: u64, // Secuencia del origen (para la ruta de vuelta)
+}
+
+/// 📬 RREP (unicast por la ruta de vuelta): la manda el destino buscado, que es el src_id
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RouteReply {
+    pub seq: u64, // Secuencia del destino (para la ruta de ida)
+}
+
+/// 💔 RERR (solo a los vecinos, TTL 1): rutas que se rompieron, con la secuencia (impar) de la caída
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RouteError {
+    pub unreachable: Vec<AdvertEntry>,
+}
+
+pub struct Route {
+    pub next_hop: SocketAddr,
+    pub metric: u32,
+    pub seq: u64,
+    updated: Instant,
+}
+
+pub struct RouteTable {
+    my_id: [u8; 8],
+    my_seq: u64,
+    routes: HashMap<[u8; 8], Route>,
+    on_demand: bool,
+    broken: Vec<AdvertEntry>, // Caídas aún no avisadas con un RERR
+    pending: HashMap<[u8; 8], (Instant, Vec<Frame>)>, // Tramas esperando una ruta
+}
+
+impl RouteTable {
+    pub fn new(my_id: [u8; 8], on_demand: bool) -> Self {
+        // La secuencia arranca del reloj: tras reiniciar seguimos por encima de la vieja
+        let my_seq = protocol::unix_millis() / 1000 * 2;
+        Self { my_id, my_seq, routes: HashMap::new(), on_demand, broken: Vec::new(), pending: HashMap::new() }
+    }
+
+    /// true = sin anuncios periódicos: las rutas se buscan con RREQ al necesitarlas
+    pub fn is_on_demand(&self) -> bool {
+        self.on_demand
+    }
+
+    /// Estrena una secuencia propia (para un anuncio, un RREQ o un RREP)
+    pub fn next_seq(&mut self) -> u64 {
+        self.my_seq += 2;
+        self.my_seq
+    }
+
+    /// Nuestro próximo anuncio (cada uno estrena secuencia propia)
+    pub fn advert(&mut self) -> RouteAdvert {
+        let seq = self.next_seq();
+        let routes = self.routes.iter()
+            .map(|(dest, r)| AdvertEntry { dest: *dest, metric: r.metric, seq: r.seq })
+            .collect();
+        RouteAdvert { seq, routes }
+    }
+
+    /// Incorpora el anuncio de un vecino, cuyo enlace cuesta `link_cost` (ETX redondeado,
+    /// así un enlace con pérdidas pesa como varios saltos). Devuelve true si cambió alguna ruta.
+    pub fn on_advert(&mut self, neighbor_id: [u8; 8], from: SocketAddr, link_cost: u32, advert: &RouteAdvert) -> bool {
+        let mut changed = self.offer(neighbor_id, from, link_cost.min(INFINITY), advert.seq);
+        for entry in &advert.routes {
+            if entry.dest == self.my_id || entry.dest == neighbor_id { continue; }
+            let metric = entry.metric.saturating_add(link_cost).min(INFINITY);
+            changed |= self.offer(entry.dest, from, metric, entry.seq);
+        }
+        changed
+    }
+
+    /// Ruta aprendida de un RREQ (hacia el origen) o de un RREP (hacia el destino)
+    pub fn learn(&mut self, dest: [u8; 8], via: SocketAddr, metric: u32, seq: u64) -> bool {
+        if dest == self.my_id { return false; }
+        self.offer(dest, via, metric.min(INFINITY), seq)
+    }
+
+    /// Tráfico que sigue usando una ruta la mantiene viva
+    pub fn refresh(&mut self, dest: &[u8; 8], via: SocketAddr) {
+        if let Some(route) = self.routes.get_mut(dest).filter(|r| r.next_hop == via && r.metric < INFINITY) {
+            route.updated = Instant::now();
+        }
+    }
+
+    /// Aplica un RERR del vecino `from`: rompe solo las rutas que pasan por él y tienen
+    /// información más vieja que la caída. Las que se rompen quedan para avisarlas a
+    /// nuestros vecinos (ver `take_errors`); las demás rutas siguen sirviendo.
+    pub fn on_error(&mut self, from: SocketAddr, error: &RouteError) -> bool {
+        let mut changed = false;
+        for entry in &error.unreachable {
+            if let Some(route) = self.routes.get_mut(&entry.dest)
+                .filter(|r| r.next_hop == from && r.seq < entry.seq && r.metric < INFINITY) {
+                route.metric = INFINITY;
+                route.seq = entry.seq;
+                route.updated = Instant::now();
+                self.broken.push(AdvertEntry { dest: entry.dest, metric: INFINITY, seq: entry.seq });
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    fn offer(&mut self, dest: [u8; 8], via: SocketAddr, metric: u32, seq: u64) -> bool {
+        let now = Instant::now();
+        if let Some(r) = self.routes.get_mut(&dest) {
+            // Secuencia más nueva, o igual con menos saltos; lo que diga nuestro
+            // siguiente salto actual siempre vale (aunque la ruta empeore)
+            let accept = seq > r.seq || (seq == r.seq && (metric < r.metric || r.next_hop == via));
+            if !accept { return false; }
+            let changed = r.next_hop != via || r.metric != metric || r.seq != seq;
+            *r = Route { next_hop: via, metric, seq, updated: now };
+            return changed;
+        }
+        if metric >= INFINITY { return false; }
+        self.routes.insert(dest, Route { next_hop: via, metric, seq, updated: now });
+        true
+    }
+
+    /// Un vecino se cayó: todo lo que pasaba por él queda roto (secuencia impar)
+    pub fn link_down(&mut self, addr: SocketAddr) {
+        for (dest, route) in self.routes.iter_mut().filter(|(_, r)| r.next_hop == addr && r.metric < INFINITY) {
+            route.metric = INFINITY;
+            route.seq |= 1;
+            route.updated = Instant::now();
+            self.broken.push(AdvertEntry { dest: *dest, metric: INFINITY, seq: route.seq });
+        }
+    }
+
+    /// Caídas detectadas desde la última vez (para anunciarlas en un RERR)
+    pub fn take_errors(&mut self) -> Vec<AdvertEntry> {
+        std::mem::take(&mut self.broken)
+    }
+
+    /// Guarda una trama hasta tener ruta. Devuelve true si hay que lanzar un RREQ
+    /// (la primera que espera por ese destino).
+    pub fn queue(&mut self, dest: [u8; 8], frame: Frame) -> bool {
+        let first = !self.pending.contains_key(&dest);
+        let (_, frames) = self.pending.entry(dest).or_insert_with(|| (Instant::now(), Vec::new()));
+        if frames.len() < MAX_PENDING { frames.push(frame); }
+        first
+    }
+
+    /// Tramas cuyo destino ya tiene ruta, con su siguiente salto
+    pub fn take_ready(&mut self) -> Vec<(SocketAddr, Frame)> {
+        let ready: Vec<[u8; 8]> = self.pending.keys().filter(|dest| self.next_hop(dest).is_some()).cloned().collect();
+        let mut out = Vec::new();
+        for dest in ready {
+            let next_hop = self.routes[&dest].next_hop;
+            let (_, frames) = self.pending.remove(&dest).unwrap();
+            out.extend(frames.into_iter().map(|f| (next_hop, f)));
+        }
+        out
+    }
+
+    /// Búsquedas sin respuesta a tiempo: (destino, tramas descartadas)
+    pub fn expire_pending(&mut self) -> Vec<([u8; 8], usize)> {
+        let now = Instant::now();
+        let expired: Vec<[u8; 8]> = self.pending.iter()
+            .filter(|(_, (since, _))| now.duration_since(*since) > DISCOVERY_TIMEOUT)
+            .map(|(dest, _)| *dest)
+            .collect();
+        expired.into_iter().map(|dest| (dest, self.pending.remove(&dest).unwrap().1.len())).collect()
+    }
+
+    /// Rutas sin refrescar se marcan rotas; las rotas viejas se olvidan
+    pub fn expire(&mut self) {
+        let now = Instant::now();
+        for route in self.routes.values_mut() {
+            if route.metric < INFINITY && now.duration_since(route.updated) > ROUTE_TIMEOUT {
+                route.metric = INFINITY;
+                route.seq |= 1;
+                route.updated = now;
+            }
+        }
+        self.routes.retain(|_, r| r.metric < INFINITY || now.duration_since(r.updated) < ROUTE_TIMEOUT);
+    }
+
+    pub fn next_hop(&self, dest: &[u8; 8]) -> Option<&Route> {
+        self.routes.get(dest).filter(|r| r.metric < INFINITY)
+    }
+
+    /// Rutas vigentes (para /routes)
+    pub fn routes(&self) -> impl Iterator<Item = (&[u8; 8], &Route)> {
+        self.routes.iter().filter(|(_, r)| r.metric < INFINITY)
+    }
+}
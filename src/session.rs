@@ -0,0 +1,439 @@
+// 🔁 Sesiones de DM con doble trinquete (Double Ratchet)
+//
+// Cada mensaje directo usa una llave de un solo uso. Las llaves avanzan con una
+// cadena de hashes y, cada vez que el otro extremo contesta, se mezcla un nuevo
+// secreto X25519 efímero. Los secretos viejos se borran: robar la identidad
+// más tarde no permite descifrar DMs capturados antes.
+//
+// Roles: el nodo con el ID menor inicia (usa un efímero contra la llave estática
+// del otro). El que responde puede escribir primero por una cadena inicial
+// derivada de la llave del par; esos primeros mensajes solo dependen de las
+// llaves estáticas hasta que llega la primera respuesta.
+
+use crate::crypto;
+use crate::profile;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const MAX_SKIP: u32 = 1000;           // Mensajes perdidos que aceptamos saltar en una cadena
+const MAX_SKIPPED_KEYS: usize = 2000; // Llaves guardadas para mensajes fuera de orden
+const MAX_SKIPPED_STEPS: usize = 4;   // Pasos del trinquete (llaves dh del otro) cuyas llaves saltadas guardamos
+
+/// Lo que viaja (dentro del payload) en cada DM
+#[derive(Serialize, Deserialize)]
+pub struct RatchetMessage {
+    pub dh: [u8; 32], // Llave de trinquete actual del emisor
+    pub pn: u32,      // Largo de la cadena de envío anterior
+    pub n: u32,       // Número de mensaje en la cadena actual
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Session {
+    root_key: [u8; 32],
+    // None = seguimos usando nuestra llave estática (responder antes de la primera respuesta).
+    // Nunca se guarda el secreto estático en disco.
+    dh_self: Option<[u8; 32]>,
+    dh_remote: Option<[u8; 32]>,
+    send_chain: Option<[u8; 32]>,
+    recv_chain: Option<[u8; 32]>,
+    send_n: u32,
+    recv_n: u32,
+    prev_send_n: u32,
+    skipped: Vec<([u8; 32], u32, [u8; 32])>, // (dh, n, llave de mensaje)
+    #[serde(default)]
+    past_remotes: Vec<[u8; 32]>, // Últimas llaves dh del otro que quedaron atrás (la más nueva al final)
+}
+
+/// Material estático con el que se inicia una sesión
+pub struct PeerKeys {
+    pub my_id: [u8; 8],
+    pub peer_id: [u8; 8],
+    pub peer_static: [u8; 32],
+    pub pair_key: [u8; 32],
+}
+
+impl Session {
+    fn new(keys: &PeerKeys) -> Self {
+        let initial_chain = kdf_initial(&keys.pair_key);
+        if keys.my_id < keys.peer_id {
+            // Iniciador: primer paso del trinquete contra la llave estática del otro
+            let eph = StaticSecret::random_from_rng(OsRng);
+            let shared = eph.diffie_hellman(&PublicKey::from(keys.peer_static));
+            let (root_key, send_chain) = kdf_rk(&keys.pair_key, shared.as_bytes());
+            Self {
+                root_key,
+                dh_self: Some(eph.to_bytes()),
+                dh_remote: Some(keys.peer_static),
+                send_chain: Some(send_chain),
+                recv_chain: Some(initial_chain),
+                send_n: 0, recv_n: 0, prev_send_n: 0,
+                skipped: Vec::new(),
+                past_remotes: Vec::new(),
+            }
+        } else {
+            Self {
+                root_key: keys.pair_key,
+                dh_self: None,
+                dh_remote: None,
+                send_chain: Some(initial_chain),
+                recv_chain: None,
+                send_n: 0, recv_n: 0, prev_send_n: 0,
+                skipped: Vec::new(),
+                past_remotes: Vec::new(),
+            }
+        }
+    }
+
+    fn self_secret(&self, my_static: &StaticSecret) -> StaticSecret {
+        match self.dh_self {
+            Some(bytes) => StaticSecret::from(bytes),
+            None => my_static.clone(),
+        }
+    }
+
+    fn encrypt(&mut self, my_static: &StaticSecret, plaintext: &[u8]) -> Option<RatchetMessage> {
+        let (next_chain, message_key) = kdf_ck(&self.send_chain?);
+        self.send_chain = Some(next_chain);
+        let msg = RatchetMessage {
+            dh: PublicKey::from(&self.self_secret(my_static)).to_bytes(),
+            pn: self.prev_send_n,
+            n: self.send_n,
+            ciphertext: crypto::encrypt_with(&message_key, plaintext),
+        };
+        self.send_n += 1;
+        Some(msg)
+    }
+
+    fn decrypt(&mut self, my_static: &StaticSecret, msg: &RatchetMessage) -> Option<Vec<u8>> {
+        // ¿Mensaje atrasado de una cadena anterior?
+        if let Some(pos) = self.skipped.iter().position(|(dh, n, _)| *dh == msg.dh && *n == msg.n) {
+            let (_, _, message_key) = self.skipped.remove(pos);
+            return crypto::decrypt_with(&message_key, &msg.ciphertext);
+        }
+
+        if self.dh_remote != Some(msg.dh) {
+            self.skip_until(msg.pn)?;
+            self.dh_ratchet(my_static, msg.dh);
+        }
+        self.skip_until(msg.n)?;
+
+        let (next_chain, message_key) = kdf_ck(&self.recv_chain?);
+        self.recv_chain = Some(next_chain);
+        self.recv_n += 1;
+        crypto::decrypt_with(&message_key, &msg.ciphertext)
+    }
+
+    /// Guarda las llaves de los mensajes que todavía no llegaron
+    fn skip_until(&mut self, until: u32) -> Option<()> {
+        let Some(mut chain) = self.recv_chain else { return Some(()) };
+        if until.saturating_sub(self.recv_n) > MAX_SKIP { return None; }
+        let dh = self.dh_remote?;
+        while self.recv_n < until {
+            let (next_chain, message_key) = kdf_ck(&chain);
+            self.skipped.push((dh, self.recv_n, message_key));
+            chain = next_chain;
+            self.recv_n += 1;
+        }
+        self.recv_chain = Some(chain);
+        if self.skipped.len() > MAX_SKIPPED_KEYS {
+            let excess = self.skipped.len() - MAX_SKIPPED_KEYS;
+            self.skipped.drain(..excess);
+        }
+        Some(())
+    }
+
+    /// Las llaves saltadas caducan por paso del trinquete: solo quedan las de las últimas
+    /// MAX_SKIPPED_STEPS llaves dh del otro. Un mensaje que llega tantos turnos tarde se pierde.
+    fn expire_skipped(&mut self) {
+        if let Some(old) = self.dh_remote {
+            self.past_remotes.retain(|dh| *dh != old);
+            self.past_remotes.push(old);
+        }
+        if self.past_remotes.len() > MAX_SKIPPED_STEPS {
+            let excess = self.past_remotes.len() - MAX_SKIPPED_STEPS;
+            self.past_remotes.drain(..excess);
+        }
+        let past = &self.past_remotes;
+        self.skipped.retain(|(dh, _, _)| past.contains(dh));
+    }
+
+    fn dh_ratchet(&mut self, my_static: &StaticSecret, remote: [u8; 32]) {
+        self.expire_skipped();
+        let remote_pub = PublicKey::from(remote);
+        self.prev_send_n = self.send_n;
+        self.send_n = 0;
+        self.recv_n = 0;
+        self.dh_remote = Some(remote);
+
+        let shared = self.self_secret(my_static).diffie_hellman(&remote_pub);
+        let (root_key, recv_chain) = kdf_rk(&self.root_key, shared.as_bytes());
+        self.recv_chain = Some(recv_chain);
+
+        // Nuevo efímero: el anterior se olvida aquí (secreto hacia adelante)
+        let eph = StaticSecret::random_from_rng(OsRng);
+        let shared = eph.diffie_hellman(&remote_pub);
+        let (root_key, send_chain) = kdf_rk(&root_key, shared.as_bytes());
+        self.root_key = root_key;
+        self.send_chain = Some(send_chain);
+        self.dh_self = Some(eph.to_bytes());
+    }
+}
+
+/// Sesiones de trinquete, una por nodo, guardadas en disco (un archivo por nodo)
+pub struct SessionStore {
+    dir: PathBuf,
+    sessions: HashMap<[u8; 8], Session>,
+}
+
+impl SessionStore {
+    pub fn load(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        let mut sessions = HashMap::new();
+        if let Ok(entries) = fs::read_dir(&dir) {
+            // 🔒 Perfiles viejos guardaban las sesiones con los permisos por defecto
+            let _ = profile::private_dir(&dir);
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("json") { continue; }
+                #[cfg(unix)]
+                let _ = fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o600));
+                let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+                let Ok(id_bytes) = hex::decode(stem) else { continue };
+                let Ok(peer_id) = <[u8; 8]>::try_from(id_bytes) else { continue };
+                if let Some(session) = fs::read_to_string(&path).ok().and_then(|json| serde_json::from_str(&json).ok()) {
+                    sessions.insert(peer_id, session);
+                }
+            }
+        }
+        Self { dir, sessions }
+    }
+
+    pub fn peer_ids(&self) -> impl Iterator<Item = &[u8; 8]> {
+        self.sessions.keys()
+    }
+
+    /// Borra la sesión con un nodo (p. ej. si su llave fue revocada)
+    pub fn forget(&mut self, peer_id: &[u8; 8]) {
+        if self.sessions.remove(peer_id).is_some() {
+            let _ = fs::remove_file(self.path_for(peer_id));
+        }
+    }
+
+    /// Cifra un DM. Sin sesión previa hace falta el material estático del par.
+    pub fn encrypt(&mut self, peer_id: [u8; 8], my_static: &StaticSecret, keys: Option<PeerKeys>, plaintext: &[u8]) -> Option<Vec<u8>> {
+        let mut session = match self.sessions.get(&peer_id) {
+            Some(s) => s.clone(),
+            None => Session::new(&keys?),
+        };
+        let msg = session.encrypt(my_static, plaintext)?;
+        self.commit(peer_id, session);
+        bincode::serialize(&msg).ok()
+    }
+
+    /// Descifra un DM. Si la sesión guardada no sirve (el otro la perdió y
+    /// empezó de cero), probamos con una sesión nueva antes de rendirnos.
+    pub fn decrypt(&mut self, peer_id: [u8; 8], my_static: &StaticSecret, keys: Option<PeerKeys>, data: &[u8]) -> Option<Vec<u8>> {
+        let msg: RatchetMessage = bincode::deserialize(data).ok()?;
+
+        if let Some(existing) = self.sessions.get(&peer_id) {
+            let mut session = existing.clone();
+            if let Some(plaintext) = session.decrypt(my_static, &msg) {
+                self.commit(peer_id, session);
+                return Some(plaintext);
+            }
+        }
+
+        let mut session = Session::new(&keys?);
+        let plaintext = session.decrypt(my_static, &msg)?;
+        self.commit(peer_id, session);
+        Some(plaintext)
+    }
+
+    fn commit(&mut self, peer_id: [u8; 8], session: Session) {
+        // Las llaves de cadena van en claro: solo las lee el dueño del perfil
+        let _ = profile::private_dir(&self.dir);
+        if let Ok(json) = serde_json::to_string(&session) {
+            let _ = profile::write_private(&self.path_for(&peer_id), &json);
+        }
+        self.sessions.insert(peer_id, session);
+    }
+
+    fn path_for(&self, peer_id: &[u8; 8]) -> PathBuf {
+        self.dir.join(format!("{}.json", hex::encode(peer_id)))
+    }
+}
+
+// --- Derivación de llaves ---
+
+fn kdf_rk(root_key: &[u8; 32], dh_output: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let hk = Hkdf::<Sha256>::new(Some(root_key), dh_output);
+    let mut okm = [0u8; 64];
+    hk.expand(b"EMBER-RATCHET-ROOT", &mut okm).expect("Largo HKDF válido");
+    let mut root = [0u8; 32];
+    let mut chain = [0u8; 32];
+    root.copy_from_slice(&okm[..32]);
+    chain.copy_from_slice(&okm[32..]);
+    (root, chain)
+}
+
+/// Devuelve (siguiente llave de cadena, llave de mensaje)
+fn kdf_ck(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let step = |tag: u8| -> [u8; 32] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(chain_key).expect("HMAC acepta cualquier largo");
+        mac.update(&[tag]);
+        mac.finalize().into_bytes().into()
+    };
+    (step(0x02), step(0x01))
+}
+
+fn kdf_initial(pair_key: &[u8; 32]) -> [u8; 32] {
+    let hk = Hkdf::<Sha256>::new(None, pair_key);
+    let mut chain = [0u8; 32];
+    hk.expand(b"EMBER-RATCHET-RESPONDER-INIT", &mut chain).expect("Largo HKDF válido");
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Peer {
+        id: [u8; 8],
+        secret: StaticSecret,
+        store: SessionStore,
+    }
+
+    impl Peer {
+        fn new(id: u8, tag: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("ember-session-{}-{}-{}", std::process::id(), tag, id));
+            let _ = fs::remove_dir_all(&dir);
+            Self { id: [id; 8], secret: StaticSecret::random_from_rng(OsRng), store: SessionStore::load(dir) }
+        }
+
+        fn keys_for(&self, other: &Peer) -> PeerKeys {
+            let other_public = PublicKey::from(&other.secret);
+            PeerKeys {
+                my_id: self.id,
+                peer_id: other.id,
+                peer_static: other_public.to_bytes(),
+                pair_key: *self.secret.diffie_hellman(&other_public).as_bytes(),
+            }
+        }
+
+        fn send(&mut self, to: &Peer, text: &str) -> Vec<u8> {
+            let keys = self.keys_for(to);
+            self.store.encrypt(to.id, &self.secret, Some(keys), text.as_bytes()).expect("cifra")
+        }
+
+        fn recv(&mut self, from: &Peer, data: &[u8]) -> Option<String> {
+            let keys = self.keys_for(from);
+            self.store.decrypt(from.id, &self.secret, Some(keys), data).map(|p| String::from_utf8(p).unwrap())
+        }
+    }
+
+    impl Drop for Peer {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.store.dir);
+        }
+    }
+
+    #[test]
+    fn initiator_and_responder_round_trip() {
+        let (mut a, mut b) = (Peer::new(1, "roundtrip"), Peer::new(2, "roundtrip"));
+        for i in 0..3 {
+            let msg = a.send(&b, &format!("hola {}", i));
+            assert_eq!(b.recv(&a, &msg).as_deref(), Some(format!("hola {}", i).as_str()));
+            let reply = b.send(&a, &format!("chau {}", i));
+            assert_eq!(a.recv(&b, &reply).as_deref(), Some(format!("chau {}", i).as_str()));
+        }
+    }
+
+    #[test]
+    fn responder_writes_first() {
+        let (mut a, mut b) = (Peer::new(1, "respfirst"), Peer::new(2, "respfirst"));
+        let first = b.send(&a, "primero");
+        let second = b.send(&a, "segundo");
+        assert_eq!(a.recv(&b, &first).as_deref(), Some("primero"));
+        assert_eq!(a.recv(&b, &second).as_deref(), Some("segundo"));
+        let reply = a.send(&b, "recibido");
+        assert_eq!(b.recv(&a, &reply).as_deref(), Some("recibido"));
+        let after = b.send(&a, "ya con trinquete");
+        assert_eq!(a.recv(&b, &after).as_deref(), Some("ya con trinquete"));
+    }
+
+    #[test]
+    fn out_of_order_across_dh_ratchet() {
+        let (mut a, mut b) = (Peer::new(1, "ooo"), Peer::new(2, "ooo"));
+        let a1 = a.send(&b, "a1");
+        let a2 = a.send(&b, "a2");
+        assert_eq!(b.recv(&a, &a1).as_deref(), Some("a1"));
+        let b1 = b.send(&a, "b1");
+        assert_eq!(a.recv(&b, &b1).as_deref(), Some("b1"));
+        // a3 ya va con la llave de trinquete nueva; a2 quedó en la cadena anterior
+        let a3 = a.send(&b, "a3");
+        assert_eq!(b.recv(&a, &a3).as_deref(), Some("a3"));
+        assert_eq!(b.recv(&a, &a2).as_deref(), Some("a2"));
+        let b2 = b.send(&a, "b2");
+        assert_eq!(a.recv(&b, &b2).as_deref(), Some("b2"));
+    }
+
+    #[test]
+    fn skipped_keys_expire_after_ratchet_steps() {
+        let (mut a, mut b) = (Peer::new(1, "expire"), Peer::new(2, "expire"));
+        let first = a.send(&b, "hola");
+        assert_eq!(b.recv(&a, &first).as_deref(), Some("hola"));
+        let reply = b.send(&a, "hola");
+        assert_eq!(a.recv(&b, &reply).as_deref(), Some("hola"));
+        // a2 se pierde en el camino: B guarda su llave al recibir a3
+        let late = a.send(&b, "a2");
+        let a3 = a.send(&b, "a3");
+        assert_eq!(b.recv(&a, &a3).as_deref(), Some("a3"));
+        for i in 0..=MAX_SKIPPED_STEPS {
+            let ping = b.send(&a, &format!("ping {}", i));
+            assert_eq!(a.recv(&b, &ping).as_deref(), Some(format!("ping {}", i).as_str()));
+            let pong = a.send(&b, &format!("pong {}", i));
+            assert_eq!(b.recv(&a, &pong).as_deref(), Some(format!("pong {}", i).as_str()));
+        }
+        assert!(b.store.sessions[&a.id].skipped.is_empty());
+        assert_eq!(b.recv(&a, &late), None);
+        // La sesión sigue sana
+        let after = a.send(&b, "sigo");
+        assert_eq!(b.recv(&a, &after).as_deref(), Some("sigo"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn session_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let (mut a, mut b) = (Peer::new(1, "perms"), Peer::new(2, "perms"));
+        let msg = a.send(&b, "secreto");
+        assert_eq!(b.recv(&a, &msg).as_deref(), Some("secreto"));
+        let dir_mode = fs::metadata(&a.store.dir).unwrap().permissions().mode() & 0o777;
+        let file_mode = fs::metadata(a.store.path_for(&b.id)).unwrap().permissions().mode() & 0o777;
+        assert_eq!((dir_mode, file_mode), (0o700, 0o600));
+    }
+
+    #[test]
+    fn recovers_when_one_side_loses_its_session() {
+        let (mut a, mut b) = (Peer::new(1, "lost"), Peer::new(2, "lost"));
+        let msg = a.send(&b, "antes");
+        assert_eq!(b.recv(&a, &msg).as_deref(), Some("antes"));
+        let reply = b.send(&a, "respuesta");
+        assert_eq!(a.recv(&b, &reply).as_deref(), Some("respuesta"));
+
+        // A pierde su sesión y empieza de cero; B todavía tiene la vieja
+        a.store.forget(&b.id);
+        let fresh = a.send(&b, "de nuevo");
+        assert_eq!(b.recv(&a, &fresh).as_deref(), Some("de nuevo"));
+        let reply = b.send(&a, "te leo");
+        assert_eq!(a.recv(&b, &reply).as_deref(), Some("te leo"));
+    }
+}
@@ -1,28 +1,109 @@
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
 use std::net::UdpSocket;
 use std::sync::Arc;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::time::Duration;
+
+// 📡 Descubrimiento en la LAN: todos los nodos escuchan el mismo puerto y grupo multicast
+const DISCOVERY_PORT: u16 = 4747;
+const DISCOVERY_V4: Ipv4Addr = Ipv4Addr::new(239, 255, 77, 77); // Ámbito local de la organización
+const DISCOVERY_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x4d45); // Enlace local
+pub const BEACON_INTERVAL: Duration = Duration::from_secs(10);
 
 #[derive(Clone)]
 pub struct Transport {
     socket: Arc<UdpSocket>,
+    dual_stack: bool, // 🌐 [::] que también atiende IPv4 (como ::ffff:a.b.c.d)
 }
 
 impl Transport {
+    /// Doble pila ([::] para IPv4 e IPv6); si el sistema no tiene IPv6, solo 0.0.0.0
     pub fn bind(port: u16) -> Self {
-        let addr = format!("0.0.0.0:{}", port);
-        let socket = UdpSocket::bind(&addr).expect("No se pudo enlazar al puerto UDP");
+        let (socket, dual_stack) = match Self::bind_dual_stack(port) {
+            Ok(socket) => (socket, true),
+            Err(e) => {
+                println!("⚠️ Sin IPv6 ({}): escuchando solo en IPv4", e);
+                let addr = format!("0.0.0.0:{}", port);
+                (UdpSocket::bind(&addr).expect("No se pudo enlazar al puerto UDP"), false)
+            },
+        };
         
         // Configuración importante para rendimiento
         socket.set_nonblocking(true).expect("Fallo al poner en no-bloqueante");
 
         Self {
             socket: Arc::new(socket),
+            dual_stack,
         }
     }
 
+    fn bind_dual_stack(port: u16) -> std::io::Result<UdpSocket> {
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_only_v6(false)?; // En Windows viene en true
+        socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+        Ok(socket.into())
+    }
+
+    /// Sockets (bloqueantes) que reciben los beacons de la LAN, uno por familia.
+    /// SO_REUSEPORT: varios nodos en la misma máquina escuchan el mismo puerto.
+    pub fn join_discovery() -> Vec<Self> {
+        let mut sockets = Vec::new();
+        match Self::multicast_socket(Domain::IPV4, SocketAddr::from((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)), |s| s.join_multicast_v4(&DISCOVERY_V4, &Ipv4Addr::UNSPECIFIED)) {
+            Ok(socket) => sockets.push(socket),
+            Err(e) => println!("⚠️ Descubrimiento IPv4 no disponible: {}", e),
+        }
+        // El grupo v6 es de enlace local: hay que unirse en cada interfaz
+        match Self::multicast_socket(Domain::IPV6, SocketAddr::from((Ipv6Addr::UNSPECIFIED, DISCOVERY_PORT)), |s| {
+            let joined = interface_indices().into_iter().filter(|i| s.join_multicast_v6(&DISCOVERY_V6, *i).is_ok()).count();
+            if joined == 0 { s.join_multicast_v6(&DISCOVERY_V6, 0)?; }
+            Ok(())
+        }) {
+            Ok(socket) => sockets.push(socket),
+            Err(e) => println!("⚠️ Descubrimiento IPv6 no disponible: {}", e),
+        }
+        sockets
+    }
+
+    fn multicast_socket(domain: Domain, addr: SocketAddr, join: impl FnOnce(&Socket) -> std::io::Result<()>) -> std::io::Result<Self> {
+        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        if domain == Domain::IPV6 { socket.set_only_v6(true)?; }
+        socket.bind(&addr.into())?;
+        join(&socket)?;
+        Ok(Self { socket: Arc::new(socket.into()), dual_stack: false })
+    }
+
+    /// Adónde van nuestros beacons (grupo v4 y v6)
+    pub fn discovery_targets() -> [SocketAddr; 2] {
+        [SocketAddr::from((DISCOVERY_V4, DISCOVERY_PORT)), SocketAddr::from((DISCOVERY_V6, DISCOVERY_PORT))]
+    }
+
     pub fn send(&self, data: &[u8], target: SocketAddr) {
         // Ignoramos errores de envío (UDP es fire-and-forget)
-        let _ = self.socket.send_to(data, target);
+        let _ = self.socket.send_to(data, self.native(target));
+    }
+
+    /// Un beacon a un grupo multicast. El de IPv6 es de enlace local: sale una copia por interfaz.
+    pub fn send_multicast(&self, data: &[u8], group: SocketAddr) {
+        if group.is_ipv4() || !self.dual_stack {
+            self.send(data, group);
+            return;
+        }
+        let socket = SockRef::from(&*self.socket);
+        for index in interface_indices() {
+            if socket.set_multicast_if_v6(index).is_ok() { self.send(data, group); }
+        }
+        let _ = socket.set_multicast_if_v6(0);
+    }
+
+    /// En doble pila las direcciones IPv4 se escriben como ::ffff:a.b.c.d
+    fn native(&self, addr: SocketAddr) -> SocketAddr {
+        match addr {
+            SocketAddr::V4(v4) if self.dual_stack => SocketAddr::from((v4.ip().to_ipv6_mapped(), v4.port())),
+            _ => addr,
+        }
     }
 
     pub fn recv(&self) -> Option<(Vec<u8>, SocketAddr)> {
@@ -34,7 +115,7 @@ impl Transport {
         match self.socket.recv_from(&mut buf) {
             Ok((amt, src)) => {
                 // Devolvemos solo la parte que tiene datos
-                Some((buf[..amt].to_vec(), src))
+                Some((buf[..amt].to_vec(), canonical(src)))
             },
             Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
                 None // No hay mensajes, todo bien
@@ -49,6 +130,92 @@ impl Transport {
     pub fn try_clone(&self) -> Self {
         Self {
             socket: self.socket.clone(),
+            dual_stack: self.dual_stack,
+        }
+    }
+}
+
+/// Una sola forma por dirección: ::ffff:a.b.c.d pasa a a.b.c.d (así `peers` no ve dos vecinos)
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => SocketAddr::from((v4, v6.port())),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// IP:PUERTO, [IPv6]:PUERTO o [fe80::1%eth0]:PUERTO (la interfaz por nombre o número)
+pub fn parse_addr(text: &str) -> Result<SocketAddr, String> {
+    let invalid = || format!("Dirección inválida: {}", text);
+    if let Ok(addr) = text.parse::<SocketAddr>() { return Ok(canonical(addr)); }
+    // [ip%interfaz]:puerto con la interfaz por nombre
+    let (host, port) = text.strip_prefix('[').and_then(|t| t.split_once("]:")).ok_or_else(invalid)?;
+    let (ip, iface) = host.split_once('%').ok_or_else(invalid)?;
+    let ip: Ipv6Addr = ip.parse().map_err(|_| invalid())?;
+    let port: u16 = port.parse().map_err(|_| invalid())?;
+    let scope_id = interface_index(iface).ok_or_else(|| format!("Interfaz desconocida: {}", iface))?;
+    Ok(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)))
+}
+
+/// 🔗 Una dirección de enlace local (fe80::/10) solo tiene sentido con su interfaz. Si nos la
+/// pasó un vecino (en su lista), está en el mismo enlace que él: usamos la interfaz por la que nos llegó.
+pub fn fix_scope(addr: SocketAddr, via: SocketAddr) -> SocketAddr {
+    match (addr, via) {
+        (SocketAddr::V6(mut v6), SocketAddr::V6(via)) if is_link_local(v6.ip()) => {
+            v6.set_scope_id(via.scope_id());
+            SocketAddr::V6(v6)
+        },
+        _ => addr,
+    }
+}
+
+fn is_link_local(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
+}
+
+/// ¿Se puede usar tal cual? (una fe80:: sin interfaz no se puede alcanzar)
+pub fn is_routable(addr: &SocketAddr) -> bool {
+    match addr.ip() {
+        IpAddr::V6(ip) => !is_link_local(&ip) || matches!(addr, SocketAddr::V6(v6) if v6.scope_id() != 0),
+        IpAddr::V4(_) => true,
+    }
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    if let Ok(index) = name.parse() { return Some(index); }
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: `name` es un C string válido durante la llamada
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+#[cfg(not(unix))]
+fn interface_index(name: &str) -> Option<u32> {
+    name.parse().ok()
+}
+
+/// Índices de todas las interfaces (para el multicast de enlace local)
+#[cfg(unix)]
+fn interface_indices() -> Vec<u32> {
+    let mut indices = Vec::new();
+    // SAFETY: recorremos el arreglo que devuelve el sistema hasta su terminador y lo liberamos
+    unsafe {
+        let list = libc::if_nameindex();
+        if list.is_null() { return indices; }
+        let mut entry = list;
+        while (*entry).if_index != 0 {
+            indices.push((*entry).if_index);
+            entry = entry.add(1);
         }
+        libc::if_freenameindex(list);
     }
+    indices
+}
+
+#[cfg(not(unix))]
+fn interface_indices() -> Vec<u32> {
+    vec![0] // La interfaz por defecto
 }
\ No newline at end of file
@@ -0,0 +1,100 @@
+use crate::profile;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 📌 Llaves fijadas en el primer contacto (Trust On First Use)
+#[derive(Serialize, Deserialize)]
+struct KnownKey {
+    pubkey_hex: String,
+    first_seen: u64, // Segundos UNIX
+    #[serde(default)]
+    verified: bool,  // ✅ Número de seguridad comparado fuera de banda (/verify)
+}
+
+pub enum KeyCheck {
+    New,      // Primera vez que vemos este ID: queda fijado
+    Match,    // Coincide con la llave fijada
+    Mismatch, // ⚠️ El ID ya tenía OTRA llave
+}
+
+pub struct KnownKeys {
+    path: PathBuf,
+    keys: HashMap<String, KnownKey>, // node_id en hex -> llave
+}
+
+impl KnownKeys {
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, String> {
+        let path = path.into();
+        let keys = profile::load_json(&path)?;
+        Ok(Self { path, keys })
+    }
+
+    /// Compara la llave con la fijada para ese ID; si el ID es nuevo, la fija y guarda
+    pub fn check_and_pin(&mut self, node_id: &[u8; 8], pubkey: &[u8; 32]) -> KeyCheck {
+        let id_hex = hex::encode(node_id);
+        let pubkey_hex = hex::encode(pubkey);
+        match self.keys.get(&id_hex) {
+            Some(known) if known.pubkey_hex == pubkey_hex => KeyCheck::Match,
+            Some(_) => KeyCheck::Mismatch,
+            None => {
+                let first_seen = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+                self.keys.insert(id_hex, KnownKey { pubkey_hex, first_seen, verified: false });
+                self.save();
+                KeyCheck::New
+            }
+        }
+    }
+
+    pub fn pubkey(&self, node_id: &[u8; 8]) -> Option<[u8; 32]> {
+        let known = self.keys.get(&hex::encode(node_id))?;
+        hex::decode(&known.pubkey_hex).ok()?.try_into().ok()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = [u8; 8]> + '_ {
+        self.keys.keys().filter_map(|id_hex| hex::decode(id_hex).ok()?.try_into().ok())
+    }
+
+    pub fn is_verified(&self, node_id: &[u8; 8]) -> bool {
+        self.keys.get(&hex::encode(node_id)).is_some_and(|k| k.verified)
+    }
+
+    /// Marca (o desmarca) un contacto como verificado. Devuelve false si no lo conocemos.
+    pub fn set_verified(&mut self, node_id: &[u8; 8], verified: bool) -> bool {
+        let Some(known) = self.keys.get_mut(&hex::encode(node_id)) else { return false };
+        known.verified = verified;
+        self.save();
+        true
+    }
+
+    fn save(&self) {
+        if let Ok(json) = serde_json::to_string_pretty(&self.keys) {
+            let _ = profile::write_atomic(&self.path, &json);
+        }
+    }
+}
+
+/// 🔢 Número de seguridad de una pareja de llaves: 60 dígitos en 12 grupos.
+/// Es el mismo en ambos extremos (las llaves se ordenan), así que dos operadores
+/// pueden leerlo en voz alta y compararlo antes de confiar el uno en el otro.
+pub fn safety_number(a: &[u8; 32], b: &[u8; 32]) -> String {
+    let (low, high) = if a <= b { (a, b) } else { (b, a) };
+    let mut hasher = Sha256::new();
+    hasher.update(b"EMBER-SAFETY-NUMBER-v1");
+    hasher.update(low);
+    hasher.update(high);
+    let digest = hasher.finalize();
+    // 12 grupos de 5 dígitos, cada uno a partir de 5 bytes del hash (30 de 32)
+    digest[..30].chunks(5)
+        .map(|c| {
+            let n = c.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+            format!("{:05}", n % 100_000)
+        })
+        .collect::<Vec<_>>()
+        .chunks(4)
+        .map(|row| row.join(" "))
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
@@ -0,0 +1,67 @@
+use crate::crypto;
+use crate::protocol::{Frame, MAGIC_BYTES, CURRENT_VERSION};
+use serde::{Serialize, Deserialize};
+use std::sync::OnceLock;
+
+// 🕶️ Modo de cabeceras selladas: la trama entera (src_id, dest_id, llave, tipo...)
+// viaja cifrada con la clave de red. Afuera solo queda lo necesario para descartar
+// otras mallas; nada identifica al destino.
+//
+// Solo protege de los que no tienen la clave de red (y por eso exige una propia, no la
+// de fábrica). Cada relay abre la trama para verificarla y reenviarla: cualquier miembro
+// de la malla ve src_id y dest_id.
+static SEAL_HEADERS: OnceLock<bool> = OnceLock::new();
+
+/// Lo que realmente viaja por UDP
+#[derive(Serialize, Deserialize)]
+enum Packet {
+    Clear(Frame),
+    Sealed(SealedFrame),
+}
+
+#[derive(Serialize, Deserialize)]
+struct SealedFrame {
+    magic: u16,
+    version: u8,
+    network_id: [u8; 8],
+    sealed: Vec<u8>, // bincode(Frame) cifrado con la clave de red
+}
+
+pub fn set_sealed_headers(enabled: bool) {
+    let _ = SEAL_HEADERS.set(enabled);
+}
+
+/// Serializa una trama para enviarla. Con cabeceras selladas se vuelve a sellar
+/// (nonce nuevo) cada vez, así un relay no deja rastro enlazable entre saltos.
+pub fn encode(frame: &Frame) -> Vec<u8> {
+    let packet = if SEAL_HEADERS.get().copied().unwrap_or(false) {
+        Packet::Sealed(SealedFrame {
+            magic: MAGIC_BYTES,
+            version: CURRENT_VERSION,
+            network_id: frame.header.network_id,
+            sealed: crypto::encrypt(&bincode::serialize(frame).unwrap()),
+        })
+    } else {
+        Packet::Clear(frame.clone())
+    };
+    bincode::serialize(&packet).unwrap()
+}
+
+pub enum Decoded {
+    Frame(Frame),
+    ForeignMesh([u8; 8]), // Sellada con otra clave de red: solo sabemos de qué red viene
+}
+
+/// Lee lo recibido por UDP (en cualquiera de los dos modos)
+pub fn decode(data: &[u8]) -> Option<Decoded> {
+    match bincode::deserialize::<Packet>(data).ok()? {
+        Packet::Clear(frame) => Some(Decoded::Frame(frame)),
+        Packet::Sealed(sealed) => {
+            if sealed.magic != MAGIC_BYTES || sealed.version != CURRENT_VERSION { return None; }
+            if sealed.network_id != crypto::network_id() { return Some(Decoded::ForeignMesh(sealed.network_id)); }
+            let frame: Frame = bincode::deserialize(&crypto::decrypt(&sealed.sealed)?).ok()?;
+            if frame.header.network_id != sealed.network_id { return None; }
+            Some(Decoded::Frame(frame))
+        },
+    }
+}